@@ -1,6 +1,6 @@
 use env_logger::fmt::TimestampPrecision;
 use ggez::*;
-use log::{debug, info};
+use log::{debug, error, info};
 mod powder;
 
 fn main() {
@@ -8,6 +8,18 @@ fn main() {
         .format_timestamp(Some(TimestampPrecision::Millis))
         .init();
     info!("Starting");
+    let config = powder::Config::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        error!("{}", err);
+        std::process::exit(1);
+    });
+    debug!("{config:?}");
+    if config.bench {
+        if let Err(err) = powder::run_benchmark(&config) {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
     // let c = conf::Conf::new();
     let (mut ctx, event_loop) = ContextBuilder::new("powder", "hersh")
         .build()
@@ -16,7 +28,7 @@ fn main() {
     debug!("Writing game config");
     // filesystem::write_config(&mut ctx, &c).expect("Couldn't write config");
     debug!("Initialising game core");
-    let game = powder::Powder::new(&mut ctx).expect("Could not run game core");
+    let game = powder::Powder::new(&mut ctx, &config).expect("Could not run game core");
 
     info!("Running event loop");
     event::run(ctx, event_loop, game)
@@ -1,8 +1,12 @@
+use std::io::Read;
+
 use ggez::{graphics::FontData, Context, GameResult};
 use log::warn;
 
 pub struct Assets {
     pub font: Option<String>,
+    // contents of the element definitions file in the resources folder, if there is one
+    pub element_definitions: Option<String>,
 }
 
 impl Assets {
@@ -16,6 +20,19 @@ impl Assets {
                     None
                 }
             },
+            element_definitions: match read_to_string(ctx, "/elements.toml") {
+                Ok(definitions) => Some(definitions),
+                Err(_) => {
+                    warn!("Failed to load element definitions! Using built-in elements");
+                    None
+                }
+            },
         })
     }
 }
+
+fn read_to_string(ctx: &Context, path: &str) -> GameResult<String> {
+    let mut contents = String::new();
+    ctx.fs.open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
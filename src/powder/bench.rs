@@ -0,0 +1,84 @@
+// A benchmark of the simulation on its own, without opening a window: fills sandboxes of a few
+// sizes with a bit of everything, then times the same run swept in turn on one thread and in
+// parallel across --threads threads, checking that both come out the same.
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::state::atom::Element;
+use super::state::registry::{ElementRegistry, RegistryError};
+use super::state::{snapshot, SandboxCoordinate, State, Tool};
+
+const SIZES: [(i32, i32); 4] = [(100, 100), (320, 180), (640, 360), (1280, 720)];
+const TICKS: u32 = 100;
+
+pub fn run_benchmark(config: &Config) -> Result<(), RegistryError> {
+    match &config.elements_path {
+        Some(path) => ElementRegistry::from_file(path)?,
+        None => ElementRegistry::default(),
+    }
+    .install()?;
+    let seed = config.seed.unwrap_or(0);
+    println!(
+        "{} ticks per run, seed {}, {} threads",
+        TICKS, seed, config.threads
+    );
+    println!(
+        "{:>10} {:>12} {:>12} {:>8}  result",
+        "size", "in turn", "parallel", "speedup"
+    );
+    for (w, h) in SIZES.iter().copied() {
+        let mut in_turn = fill(w, h, seed);
+        let mut in_parallel = in_turn.clone();
+        in_parallel.parameters.threads = config.threads;
+        let (in_turn_time, in_parallel_time) = (time(&mut in_turn), time(&mut in_parallel));
+        println!(
+            "{:>10} {:>9.2} ms {:>9.2} ms {:>7.2}x  {}",
+            format!("{}x{}", w, h),
+            per_tick(in_turn_time),
+            per_tick(in_parallel_time),
+            in_turn_time.as_secs_f64() / in_parallel_time.as_secs_f64(),
+            if saved(&in_turn) == saved(&in_parallel) {
+                "same"
+            } else {
+                "DIFFERENT"
+            }
+        );
+    }
+    Ok(())
+}
+
+fn fill(w: i32, h: i32, seed: u64) -> State {
+    // the bottom three quarters of the sandbox in blocks of every element in turn, with every
+    // third cell left empty so there's room to move
+    let mut state = State::new(w, h);
+    state.set_seed(seed);
+    let elements: Vec<Element> = ElementRegistry::global().elements().collect();
+    for y in h / 4..h {
+        for x in 0..w {
+            if (x + y) % 3 != 0 {
+                let element = elements[((x / 7 + y / 5) as usize) % elements.len()];
+                state.set_active_tool(Tool::Element(element));
+                state.make_atom(SandboxCoordinate { x, y }).ok();
+            }
+        }
+    }
+    state
+}
+
+fn time(state: &mut State) -> Duration {
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        state.update_atoms();
+    }
+    start.elapsed()
+}
+
+fn per_tick(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0 / TICKS as f64
+}
+
+fn saved(state: &State) -> Vec<u8> {
+    let mut bytes = vec![];
+    snapshot::save(state, &mut bytes).expect("Couldn't save snapshot to memory");
+    bytes
+}
@@ -0,0 +1,170 @@
+// Startup configuration, parsed from the command line:
+//     powder --size 320x180 --seed 42 --snapshot sandbox.pwdr --tps 60 --elements elements.toml
+//         --gravity 1.5 --threads 4
+// or, to time the simulation without opening a window:
+//     powder --bench --threads 4
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Unknown argument: {0}")]
+    UnknownArgument(String),
+    #[error("Missing value for {0}")]
+    MissingValue(String),
+    #[error("Invalid sandbox size {0}, expected WIDTHxHEIGHT")]
+    InvalidSize(String),
+    #[error("Invalid seed {0}, expected an unsigned integer")]
+    InvalidSeed(String),
+    #[error("Invalid tick rate {0}, expected a positive integer")]
+    InvalidTickRate(String),
+    #[error("Invalid gravity {0}, expected a number no less than 0")]
+    InvalidGravity(String),
+    #[error("Invalid thread count {0}, expected a positive integer")]
+    InvalidThreads(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub sandbox_w: i32,
+    pub sandbox_h: i32,
+    // picked at random if not given
+    pub seed: Option<u64>,
+    // where the sandbox is saved to and loaded from
+    pub snapshot_path: PathBuf,
+    // simulation ticks per second at 1x speed
+    pub ticks_per_second: u32,
+    // element definitions to use instead of the ones in the resources folder
+    pub elements_path: Option<PathBuf>,
+    // how strong gravity is, 1 being normal
+    pub gravity: f32,
+    // how many threads to sweep the sandbox with; as many as there are cores if not given
+    pub threads: usize,
+    // run the benchmark instead of the game
+    pub bench: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sandbox_w: 100,
+            sandbox_h: 100,
+            seed: None,
+            snapshot_path: PathBuf::from("sandbox.pwdr"),
+            ticks_per_second: 60,
+            elements_path: None,
+            gravity: 1.0,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            bench: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    (config.sandbox_w, config.sandbox_h) = parse_size(&value)?;
+                }
+                "--seed" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    let seed = value.parse().map_err(|_| ConfigError::InvalidSeed(value))?;
+                    config.seed = Some(seed);
+                }
+                "--snapshot" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    config.snapshot_path = PathBuf::from(value);
+                }
+                "--tps" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    config.ticks_per_second = match value.parse() {
+                        Ok(tps) if tps > 0 => tps,
+                        _ => return Err(ConfigError::InvalidTickRate(value)),
+                    };
+                }
+                "--elements" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    config.elements_path = Some(PathBuf::from(value));
+                }
+                "--gravity" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    config.gravity = match value.parse::<f32>() {
+                        Ok(gravity) if gravity.is_finite() && gravity >= 0.0 => gravity,
+                        _ => return Err(ConfigError::InvalidGravity(value)),
+                    };
+                }
+                "--threads" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+                    config.threads = match value.parse() {
+                        Ok(threads) if threads > 0 => threads,
+                        _ => return Err(ConfigError::InvalidThreads(value)),
+                    };
+                }
+                "--bench" => config.bench = true,
+                _ => return Err(ConfigError::UnknownArgument(arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_size(value: &str) -> Result<(i32, i32), ConfigError> {
+    let invalid = || ConfigError::InvalidSize(value.to_string());
+    let (w, h) = value.split_once('x').ok_or_else(invalid)?;
+    let w: i32 = w.parse().map_err(|_| invalid())?;
+    let h: i32 = h.parse().map_err(|_| invalid())?;
+    if w <= 0 || h <= 0 {
+        return Err(invalid());
+    }
+    Ok((w, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parses_rectangular_size() {
+        let config = Config::from_args(args(&["--size", "320x180"])).unwrap();
+        assert_eq!((config.sandbox_w, config.sandbox_h), (320, 180));
+    }
+
+    #[test]
+    fn parses_seed() {
+        let config = Config::from_args(args(&["--seed", "42", "--size", "8x4"])).unwrap();
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(Config::from_args(args(&[])).unwrap().seed, None);
+    }
+
+    #[test]
+    fn parses_threads_and_bench() {
+        let config = Config::from_args(args(&["--bench", "--threads", "3"])).unwrap();
+        assert!(config.bench);
+        assert_eq!(config.threads, 3);
+        assert!(!Config::from_args(args(&[])).unwrap().bench);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(Config::from_args(args(&["--size"])).is_err());
+        assert!(Config::from_args(args(&["--size", "320"])).is_err());
+        assert!(Config::from_args(args(&["--size", "0x10"])).is_err());
+        assert!(Config::from_args(args(&["--seed", "-1"])).is_err());
+        assert!(Config::from_args(args(&["--tps", "0"])).is_err());
+        assert!(Config::from_args(args(&["--elements"])).is_err());
+        assert!(Config::from_args(args(&["--gravity", "-1"])).is_err());
+        assert!(Config::from_args(args(&["--threads", "0"])).is_err());
+        assert!(Config::from_args(args(&["--bogus"])).is_err());
+    }
+}
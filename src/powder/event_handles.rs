@@ -2,22 +2,17 @@
 // Maybe this interface to main should be defined in a trait first?
 // Regardless, event handlers get access to everything the relevant EventHandler method defines.
 
+use std::path::Path;
+
 use anyhow::Error;
 
+use super::renderer::ButtonAction;
 use super::state::*;
+use super::timing::Timing;
 use super::Renderer;
 use ggez::*;
 use log::*;
 
-#[derive(Debug)]
-pub struct EventHandlerError;
-
-impl std::fmt::Display for EventHandlerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Couldn't handle event")
-    }
-}
-
 // helpers
 fn click_in_rect(x: f32, y: f32, rect: graphics::Rect) -> bool {
     x > rect.x && x < rect.x + rect.w && y > rect.y && y < rect.y + rect.h
@@ -27,55 +22,212 @@ fn convert_coord_to_sandbox_coord(renderer: &Renderer, x: f32, y: f32) -> Sandbo
     // even though state.make_atom() checks invalid mutation, check here as well that we're not
     // getting underflows or anything silly
     SandboxCoordinate {
-        x: (x - renderer.get_frame_sandbox().x) as i32 / renderer.get_scaling_factor(),
-        y: (y - renderer.get_frame_sandbox().y) as i32 / renderer.get_scaling_factor(),
+        x: ((x - renderer.get_frame_sandbox().x) / renderer.get_scaling_factor()) as i32,
+        y: ((y - renderer.get_frame_sandbox().y) / renderer.get_scaling_factor()) as i32,
     }
 }
 
+fn stroke_to(
+    last_coord: &mut Option<SandboxCoordinate>,
+    coord: SandboxCoordinate,
+) -> Vec<SandboxCoordinate> {
+    // mouse is only sampled once a frame, so fill in every cell between the last sample of this
+    // stroke and this one, or fast drags leave gaps
+    let path = match *last_coord {
+        Some(last) => brush::line(last, coord),
+        None => vec![coord],
+    };
+    *last_coord = Some(coord);
+    path
+}
+
 // handlers
 pub fn update(_ctx: &mut Context, _state: &mut State) -> Result<(), Error> {
     // debug!("Frame length: {}", timer::delta(ctx).as_millis());
     Ok(())
 }
 
-pub fn mouse_button_down_event(
+pub fn mouse_button_held(
     _ctx: &mut Context,
     state: &mut State,
     renderer: &Renderer,
+    last_coord: &mut Option<SandboxCoordinate>,
     button: input::mouse::MouseButton,
     x: f32,
     y: f32,
 ) -> GameResult {
+    // called every frame a button is held, to paint strokes; one-off clicks on the ui are
+    // handled by mouse_button_down_event instead
+    if !click_in_rect(x, y, renderer.get_frame_sandbox()) {
+        // if outside of sandbox
+        debug!("EH: Atom out of bounds, not generating");
+        *last_coord = None;
+        return Ok(());
+    }
+    let coord = convert_coord_to_sandbox_coord(renderer, x, y);
     match button {
         input::mouse::MouseButton::Left => {
-            // handle LMB
-            debug!("Handling LMB at ({}, {})", x, y);
-            // TODO: probably need to move this to the renderer because it
-            // shouldn't be the event handler's job to determine what ui has
-            // been clicked
-            if click_in_rect(x, y, renderer.get_frame_sandbox()) {
-                // if clicked in sandbox
-                let coord = convert_coord_to_sandbox_coord(&renderer, x, y);
-                info!("Making atom at ({}, {})", coord.x, coord.y);
-                state.make_atom(coord).map_err(|err| info!("{}", err)).ok();
-                Ok(())
-            } else if click_in_rect(x, y, renderer.get_frame_element_selector()) {
-                // if clicked in element selector
-                let coord = convert_coord_to_sandbox_coord(&renderer, x, y);
-                info!("Clicked in element selector at ({}, {})", coord.x, coord.y);
-                for button in renderer.get_buttons() {
-                    if click_in_rect(x, y, button.rect) {
-                        // if clicked on button
-                        state.set_active_element(button.el);
-                    }
+            info!("Using tool at ({}, {})", coord.x, coord.y);
+            // which way the mouse has moved since last frame, for the wind
+            let drag = match *last_coord {
+                Some(last) => SandboxCoordinate {
+                    x: coord.x - last.x,
+                    y: coord.y - last.y,
+                },
+                None => SandboxCoordinate { x: 0, y: 0 },
+            };
+            for coord in stroke_to(last_coord, coord) {
+                state.use_active_tool(coord, drag);
+            }
+        }
+        input::mouse::MouseButton::Right => {
+            // RMB always erases, whatever tool is active
+            info!("Erasing at ({}, {})", coord.x, coord.y);
+            for coord in stroke_to(last_coord, coord) {
+                state.erase(coord);
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+pub fn mouse_button_down_event(
+    _ctx: &mut Context,
+    state: &mut State,
+    timing: &mut Timing,
+    renderer: &mut Renderer,
+    button: input::mouse::MouseButton,
+    x: f32,
+    y: f32,
+) -> GameResult {
+    // TODO: probably need to move this to the renderer because it
+    // shouldn't be the event handler's job to determine what ui has
+    // been clicked
+    if button == input::mouse::MouseButton::Left
+        && click_in_rect(x, y, renderer.get_frame_element_selector())
+    {
+        // if clicked in element selector
+        debug!("Clicked in element selector at ({}, {})", x, y);
+        for button in renderer.get_buttons() {
+            if click_in_rect(x, y, button.rect) {
+                // if clicked on button
+                match button.action {
+                    ButtonAction::SelectTool(tool) => state.set_active_tool(tool),
+                    ButtonAction::Clear => state.clear(),
+                    ButtonAction::TogglePause => timing.toggle_pause(),
+                    ButtonAction::Step => timing.step(),
+                    ButtonAction::Slower => timing.slower(),
+                    ButtonAction::Faster => timing.faster(),
+                    ButtonAction::ToggleHeatView => renderer.toggle_heat_view(),
+                    ButtonAction::ToggleAirOverlay => renderer.toggle_air_overlay(),
+                    ButtonAction::ToggleChunkOverlay => renderer.toggle_chunk_overlay(),
+                    ButtonAction::RotateGravity => state.parameters.gravity.rotate(1),
+                    ButtonAction::ToggleZeroG => state.parameters.gravity.toggle_zero_g(),
                 }
-                Ok(())
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn key_down_event(
+    _ctx: &mut Context,
+    state: &mut State,
+    timing: &mut Timing,
+    renderer: &mut Renderer,
+    snapshot_path: &Path,
+    input: input::keyboard::KeyInput,
+) -> GameResult {
+    match input.keycode {
+        Some(input::keyboard::KeyCode::H) => {
+            renderer.toggle_heat_view();
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::A) => {
+            renderer.toggle_air_overlay();
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::K) => {
+            renderer.toggle_chunk_overlay();
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::G) => {
+            // clockwise, or anticlockwise with shift held
+            let eighths = if input.mods.contains(input::keyboard::KeyMods::SHIFT) {
+                -1
             } else {
-                // if clicked outside of sandbox
-                debug!("EH: Atom out of bounds, not generating");
-                return Ok(());
+                1
+            };
+            state.parameters.gravity.rotate(eighths);
+            info!("Gravity now pulls {}", state.parameters.gravity.direction);
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::Z) => {
+            state.parameters.gravity.toggle_zero_g();
+            info!("Zero gravity: {}", state.parameters.gravity.zero_g);
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::Space) => {
+            timing.toggle_pause();
+            info!("Paused: {}", timing.paused);
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::N) => {
+            timing.step();
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::Minus) => {
+            timing.slower();
+            info!("Simulation speed x{}", timing.speed());
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::Equals) => {
+            timing.faster();
+            info!("Simulation speed x{}", timing.speed());
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::C) => {
+            info!("Clearing sandbox");
+            state.clear();
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::S) => {
+            match snapshot::save_to_file(state, snapshot_path) {
+                Ok(()) => info!("Saved sandbox to {}", snapshot_path.display()),
+                Err(err) => warn!("{}", err),
             }
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::L) => {
+            match snapshot::load_from_file(state, snapshot_path) {
+                Ok(()) => info!("Loaded sandbox from {}", snapshot_path.display()),
+                Err(err) => warn!("{}", err),
+            }
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::LBracket) => {
+            state.brush.resize(-1);
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::RBracket) => {
+            state.brush.resize(1);
+            Ok(())
+        }
+        Some(input::keyboard::KeyCode::B) => {
+            state.brush.next_shape();
+            info!("Brush shape is now {:?}", state.brush.shape);
+            Ok(())
         }
         _ => Ok(()),
     }
 }
+
+pub fn mouse_wheel_event(_ctx: &mut Context, state: &mut State, _x: f32, y: f32) -> GameResult {
+    // scrolling up grows the brush, scrolling down shrinks it; sideways scrolling does neither
+    if y == 0.0 {
+        return Ok(());
+    }
+    state.brush.resize(y.signum() as i32);
+    Ok(())
+}
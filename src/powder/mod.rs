@@ -1,30 +1,61 @@
+use std::path::PathBuf;
+
 use ggez::*;
-use log::debug;
+use log::{debug, info};
 
 mod assets;
+mod bench;
+mod config;
 mod event_handles;
 mod renderer;
 mod state;
+mod timing;
 
 use assets::Assets;
+pub use bench::run_benchmark;
+pub use config::Config;
 use renderer::Renderer;
-use state::State;
+use state::registry::ElementRegistry;
+use state::{SandboxCoordinate, State};
+use timing::Timing;
 
 pub struct Powder {
     state: state::State,
     assets: Assets,
     renderer: Renderer,
+    timing: Timing,
+    // where the mouse was last frame while painting, so strokes can be joined up
+    last_mouse_coord: Option<SandboxCoordinate>,
+    snapshot_path: PathBuf,
 }
 
 impl Powder {
-    pub fn new(ctx: &mut Context) -> GameResult<Self> {
-        let state = State::new(100); // hardcode sandbox size for now
+    pub fn new(ctx: &mut Context, config: &Config) -> GameResult<Self> {
         let assets = Assets::new(ctx)?;
+        // elements have to be known before anything else is built
+        let registry = match (&config.elements_path, &assets.element_definitions) {
+            (Some(path), _) => ElementRegistry::from_file(path),
+            (None, Some(definitions)) => ElementRegistry::from_toml(definitions),
+            (None, None) => Ok(ElementRegistry::default()),
+        };
+        registry
+            .and_then(ElementRegistry::install)
+            .map_err(|err| GameError::CustomError(err.to_string()))?;
+        let mut state = State::new(config.sandbox_w, config.sandbox_h);
+        state.parameters.gravity.strength = config.gravity;
+        state.parameters.threads = config.threads;
+        if let Some(seed) = config.seed {
+            state.set_seed(seed);
+        }
+        info!("Simulation seed: {}", state.get_seed());
         let renderer = Renderer::new(ctx, &state, assets.font.clone());
         let mut powder = Powder {
-            state: state,
-            assets: assets,
-            renderer: renderer,
+            state,
+            assets,
+            renderer,
+            timing: Timing::new(config.ticks_per_second),
+            last_mouse_coord: None,
+            snapshot_path: config.snapshot_path.clone(),
         };
         powder.init(ctx)?;
         Ok(powder)
@@ -42,22 +73,41 @@ impl ggez::event::EventHandler<GameError> for Powder {
     /* Required methods for EventHandler trait */
     fn update(&mut self, ctx: &mut Context) -> GameResult {
         event_handles::update(ctx, &mut self.state).ok();
-        if ctx.mouse.button_pressed(input::mouse::MouseButton::Left) {
-            event_handles::mouse_button_down_event(
-                ctx,
-                &mut self.state,
-                &self.renderer,
-                input::mouse::MouseButton::Left,
-                ctx.mouse.position().x,
-                ctx.mouse.position().y,
-            )?
+        let mut painting = false;
+        for button in [
+            input::mouse::MouseButton::Left,
+            input::mouse::MouseButton::Right,
+        ] {
+            if ctx.mouse.button_pressed(button) {
+                painting = true;
+                event_handles::mouse_button_held(
+                    ctx,
+                    &mut self.state,
+                    &self.renderer,
+                    &mut self.last_mouse_coord,
+                    button,
+                    ctx.mouse.position().x,
+                    ctx.mouse.position().y,
+                )?
+            }
+        }
+        if !painting {
+            // stroke has ended, don't join the next one up to it
+            self.last_mouse_coord = None;
         }
-        self.state.update_atoms();
+        let ticks = self
+            .timing
+            .ticks_due(|rate| ctx.time.check_update_time(rate));
+        for _ in 0..ticks {
+            self.state.update_atoms();
+        }
+        self.timing.record_frame(ticks, ctx.time.delta());
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        self.renderer.draw(ctx, &self.state, &self.assets)?;
+        self.renderer
+            .draw(ctx, &self.state, &self.timing, &self.assets)?;
         timer::yield_now();
         Ok(())
     }
@@ -70,7 +120,34 @@ impl ggez::event::EventHandler<GameError> for Powder {
         x: f32,
         y: f32,
     ) -> GameResult {
-        // event_handles::mouse_button_down_event(ctx, &mut self.state, &self.renderer, button, x, y)
-        Ok(())
+        event_handles::mouse_button_down_event(
+            ctx,
+            &mut self.state,
+            &mut self.timing,
+            &mut self.renderer,
+            button,
+            x,
+            y,
+        )
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, x: f32, y: f32) -> GameResult {
+        event_handles::mouse_wheel_event(ctx, &mut self.state, x, y)
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        input: input::keyboard::KeyInput,
+        _repeated: bool,
+    ) -> GameResult {
+        event_handles::key_down_event(
+            ctx,
+            &mut self.state,
+            &mut self.timing,
+            &mut self.renderer,
+            &self.snapshot_path,
+            input,
+        )
     }
 }
@@ -1,23 +1,56 @@
 use super::assets::Assets;
-use super::state::atom::Element;
-use super::state::Atoms;
-use super::state::State;
+use super::state::air::{Air, AIR_CELL_SIZE};
+use super::state::atom::Atom;
+use super::state::brush::{Brush, BrushShape};
+use super::state::heat::AMBIENT_TEMPERATURE;
+use super::state::parameters::Gravity;
+use super::state::registry::ElementRegistry;
+use super::state::sweep::Chunk;
+use super::state::Tool;
+use super::state::{SandboxCoordinate, State};
+use super::timing::Timing;
 use ggez::{graphics::*, Context, GameResult};
-use strum::IntoEnumIterator;
 
 type Point2 = glam::Vec2;
 
+// the selector lays buttons out in a grid, so that there's room for plenty of elements
+const BUTTON_W: f32 = 84f32;
+const BUTTON_H: f32 = 26f32;
+const BUTTON_GAP: f32 = 6f32;
+const SELECTOR_COLUMNS: usize = 2;
+const SELECTOR_W: f32 = BUTTON_W * SELECTOR_COLUMNS as f32 + BUTTON_GAP;
+
 #[derive(Debug, Clone)]
 pub struct Button {
     frame: Mesh,
     text: Text,
     pub rect: Rect,
-    pub el: Element,
+    pub action: ButtonAction,
 }
 type Buttons = Vec<Button>;
 
-#[derive(Debug)]
-pub struct RendererError;
+#[derive(Debug, Clone, Copy)]
+pub enum ButtonAction {
+    SelectTool(Tool),
+    Clear,
+    TogglePause,
+    Step,
+    Slower,
+    Faster,
+    ToggleHeatView,
+    ToggleAirOverlay,
+    ToggleChunkOverlay,
+    RotateGravity,
+    ToggleZeroG,
+}
+
+// what the sandbox is drawn as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Elements,
+    // the temperature of every cell, empty or not
+    Heat,
+}
 
 #[derive(Debug)]
 pub struct Renderer {
@@ -25,94 +58,133 @@ pub struct Renderer {
     frame_fps: Rect,
     frame_element_selector: Rect,
     font: Option<String>,
-    pub scaling_factor: i32,
+    pub scaling_factor: f32,
     // TODO: use this to cache the sandbox mesh (and any other Drawables that don't need to be
     // regenerated every frame)
     mesh_sandbox: Option<Mesh>,
     buttons: Option<Buttons>,
+    view: View,
+    // pressure and wind drawn over the top of the sandbox
+    show_air: bool,
+    // outlines round the chunks that were swept on the last tick, rather than left asleep
+    show_chunks: bool,
 }
 
 impl Renderer {
     pub fn new(ctx: &Context, state: &State, font: Option<String>) -> Self {
-        // figure that the sandbox should take 80% of the smaller screen dimension?
-        // and assuming square for now
+        // figure that the sandbox should fit in 80% of the screen height and whatever width is
+        // left once there's room for the selector either side of it, scaled by the largest whole
+        // number of pixels per cell that fits in both, or by however much less than a pixel it
+        // takes if the sandbox is too big for even that
         let (win_w, win_h) = ctx.gfx.drawable_size();
-        let sandbox_size_px;
-        if win_w > win_h {
-            sandbox_size_px = (win_h * 0.8) as i32;
-        } else {
-            sandbox_size_px = (win_w * 0.8) as i32;
-        }
-        let sandbox_size_px = sandbox_size_px - sandbox_size_px % state.parameters.sandbox_w;
-        // calc scaling factor based on this -- do we still need it?
-        let scaling_factor = sandbox_size_px / state.parameters.sandbox_w;
+        let fit = f32::min(
+            (win_w - 2f32 * (SELECTOR_W + 20f32)) / state.parameters.sandbox_w as f32,
+            win_h * 0.8 / state.parameters.sandbox_h as f32,
+        );
+        let scaling_factor = if fit >= 1f32 { fit.floor() } else { fit };
+        let sandbox_w_px = state.parameters.sandbox_w as f32 * scaling_factor;
+        let sandbox_h_px = state.parameters.sandbox_h as f32 * scaling_factor;
 
         // calc sandbox frame
         let frame_sandbox = Rect::new(
-            (win_w - sandbox_size_px as f32) / 2f32,
-            (win_h - sandbox_size_px as f32) / 2f32,
-            sandbox_size_px as f32 + 1f32,
-            sandbox_size_px as f32 + 1f32,
+            (win_w - sandbox_w_px) / 2f32,
+            (win_h - sandbox_h_px) / 2f32,
+            sandbox_w_px + 1f32,
+            sandbox_h_px + 1f32,
         );
 
-        // calc fps frame
-        let fps_w = 200f32;
+        // calc fps frame, right-aligned above the sandbox
+        let fps_w = 300f32;
         let fps_h = 20f32;
         let frame_fps = Rect::new(
-            frame_sandbox.x + frame_sandbox.w,
+            frame_sandbox.x + frame_sandbox.w - fps_w,
             frame_sandbox.y - fps_h,
             fps_w,
             fps_h,
         );
 
-        // calc element buttons
+        // calc element buttons; the selector takes the full height of the window so it doesn't
+        // run out of room on short sandboxes
         let frame_element_selector = Rect::new(
             frame_sandbox.x + frame_sandbox.w + 10f32,
-            frame_sandbox.y,
-            80f32,
-            frame_sandbox.h,
+            10f32,
+            SELECTOR_W,
+            win_h - 20f32,
         );
 
         Renderer {
-            frame_sandbox: frame_sandbox,
-            frame_fps: frame_fps,
-            frame_element_selector: frame_element_selector,
-            font: font,
-            scaling_factor: scaling_factor,
+            frame_sandbox,
+            frame_fps,
+            frame_element_selector,
+            font,
+            scaling_factor,
             mesh_sandbox: None,
             buttons: None,
+            view: View::Elements,
+            show_air: false,
+            show_chunks: false,
         }
     }
 
     pub fn init(&mut self, ctx: &mut Context) {
         // some drawables don't have to be redrawn every time, so are saved in the renderer at init
         self.mesh_sandbox = Some(self.draw_sandbox(ctx, self.frame_sandbox));
-        self.buttons = Some(self.draw_element_selector(ctx));
+        let mut buttons = self.draw_element_selector(ctx);
+        buttons.append(&mut self.draw_control_buttons(ctx));
+        self.buttons = Some(buttons);
     }
 
-    pub fn get_scaling_factor(&self) -> i32 {
+    pub fn get_scaling_factor(&self) -> f32 {
         self.scaling_factor
     }
 
     pub fn get_frame_sandbox(&self) -> Rect {
-        self.frame_sandbox.clone()
+        self.frame_sandbox
     }
 
     pub fn get_frame_element_selector(&self) -> Rect {
-        self.frame_element_selector.clone()
+        self.frame_element_selector
     }
 
     pub fn get_buttons(&self) -> Buttons {
-        if let Some(buttons) = self.buttons.clone() {
-            return buttons;
-        } else {
-            return vec![];
-        }
+        self.buttons.clone().unwrap_or_default()
     }
 
-    fn draw_fps(&self, ctx: &mut Context, frame: Rect, font: &Option<String>) -> GameResult<Text> {
+    pub fn toggle_heat_view(&mut self) {
+        self.view = match self.view {
+            View::Elements => View::Heat,
+            View::Heat => View::Elements,
+        };
+    }
+
+    pub fn toggle_air_overlay(&mut self) {
+        self.show_air = !self.show_air;
+    }
+
+    pub fn toggle_chunk_overlay(&mut self) {
+        self.show_chunks = !self.show_chunks;
+    }
+
+    fn draw_fps(
+        &self,
+        ctx: &mut Context,
+        frame: Rect,
+        font: &Option<String>,
+        timing: &Timing,
+        gravity: &Gravity,
+    ) -> GameResult<Text> {
+        let sim = if timing.paused {
+            String::from("paused")
+        } else {
+            format!("{:.0} tps x{}", timing.tick_rate(), timing.speed())
+        };
+        let gravity = if gravity.zero_g {
+            String::from("zero-g")
+        } else {
+            format!("g {}", gravity.direction)
+        };
         let mut text = Text::new(TextFragment {
-            text: format!("{:.2}", ctx.time.fps()),
+            text: format!("{}  {}  {:.2}", gravity, sim, ctx.time.fps()),
             color: Some(Color::WHITE),
             font: font.clone(),
             scale: Some(PxScale::from(20.0)),
@@ -144,7 +216,7 @@ impl Renderer {
         ctx: &mut Context,
         button: Rect,
         text_str: String,
-        el: Element,
+        action: ButtonAction,
     ) -> Button {
         // button outline
         let outline = Mesh::from_data(
@@ -163,7 +235,7 @@ impl Renderer {
             text: text_str,
             color: Some(Color::WHITE),
             font: self.font.clone(),
-            scale: Some(PxScale::from(button.h - 14f32)),
+            scale: Some(PxScale::from(button.h - 12f32)),
         });
         text.set_bounds(Point2::new(button.w, button.h));
         text.set_layout(TextLayout {
@@ -172,53 +244,90 @@ impl Renderer {
         });
         Button {
             frame: outline,
-            text: text,
+            text,
             rect: button,
-            el: el,
+            action,
         }
     }
 
+    fn selector_slot(&self, index: usize, from_bottom: bool) -> Rect {
+        // where the index-th button goes, filling the selector row by row from the top or the
+        // bottom
+        let frame = self.frame_element_selector;
+        let row = (index / SELECTOR_COLUMNS) as f32;
+        let column = (index % SELECTOR_COLUMNS) as f32;
+        let y = if from_bottom {
+            frame.y + frame.h - BUTTON_H - row * (BUTTON_H + BUTTON_GAP)
+        } else {
+            frame.y + row * (BUTTON_H + BUTTON_GAP)
+        };
+        Rect::new(
+            frame.x + column * (BUTTON_W + BUTTON_GAP),
+            y,
+            BUTTON_W,
+            BUTTON_H,
+        )
+    }
+
     fn draw_element_selector(&self, ctx: &mut Context) -> Buttons {
-        let mut element_selector: Buttons = vec![];
-        // can't enumerate an enum so gotta keep an index separately
-        let mut i = 1f32;
-        for el in Element::iter() {
-            // create button drawables first
-            let button_height = 30f32;
-            let outline_rect = Rect {
-                x: self.frame_element_selector.x,
-                y: self.frame_element_selector.y + self.frame_element_selector.h // offset to bottom of selector...
-                    - button_height * i // ... account for button height
-                    - 10f32 * (i - 1f32), // ... and padding
-                w: self.frame_element_selector.w,
-                h: button_height,
-            };
-            let button = self.draw_button(ctx, outline_rect, el.to_string(), el);
-            // then add to buttons vec
-            element_selector.push(button);
-            i += 1f32;
+        // one button per element in the registry, plus the eraser, stacked up from the bottom
+        let tools = ElementRegistry::global()
+            .elements()
+            .map(Tool::Element)
+            .chain([Tool::Eraser, Tool::Pressure, Tool::Wind]);
+        tools
+            .enumerate()
+            .map(|(i, tool)| {
+                let text = match tool {
+                    Tool::Element(el) => el.to_string(),
+                    Tool::Eraser => String::from("eraser"),
+                    Tool::Pressure => String::from("pressure"),
+                    Tool::Wind => String::from("wind"),
+                };
+                let outline_rect = self.selector_slot(i, true);
+                self.draw_button(ctx, outline_rect, text, ButtonAction::SelectTool(tool))
+            })
+            .collect()
+    }
+
+    fn draw_control_buttons(&self, ctx: &mut Context) -> Buttons {
+        // control buttons stack down from the top of the selector, opposite the elements
+        let mut controls: Buttons = vec![];
+        let actions = [
+            (ButtonAction::Clear, "clear"),
+            (ButtonAction::TogglePause, "pause"),
+            (ButtonAction::Step, "step"),
+            (ButtonAction::Slower, "slower"),
+            (ButtonAction::Faster, "faster"),
+            (ButtonAction::ToggleHeatView, "heat"),
+            (ButtonAction::ToggleAirOverlay, "air"),
+            (ButtonAction::ToggleChunkOverlay, "chunks"),
+            (ButtonAction::RotateGravity, "turn g"),
+            (ButtonAction::ToggleZeroG, "zero-g"),
+        ];
+        for (i, (action, text)) in actions.iter().enumerate() {
+            let outline_rect = self.selector_slot(i, false);
+            controls.push(self.draw_button(ctx, outline_rect, text.to_string(), *action));
         }
-        return element_selector;
+        controls
     }
 
-    fn draw_atoms(
+    fn draw_atoms<'a>(
         &self,
         ctx: &mut Context,
-        atoms: &Atoms,
-        scaling_factor: i32,
+        atoms: impl Iterator<Item = (SandboxCoordinate, &'a Atom)>,
+        scaling_factor: f32,
     ) -> GameResult<Mesh> {
         // TODO: proper co-ordinate conversion
         let mb = &mut MeshBuilder::new();
-        for atom in atoms {
-            let x = atom.coord.x * scaling_factor;
-            let y = atom.coord.y * scaling_factor;
+        for (coord, atom) in atoms {
             mb.rectangle(
                 DrawMode::fill(),
                 Rect {
-                    x: x as f32,
-                    y: y as f32,
-                    w: scaling_factor as f32,
-                    h: scaling_factor as f32,
+                    x: coord.x as f32 * scaling_factor,
+                    y: coord.y as f32 * scaling_factor,
+                    w: scaling_factor,
+                    h: scaling_factor,
                 },
                 atom.color(),
             )
@@ -227,13 +336,166 @@ impl Renderer {
         Ok(Mesh::from_data(ctx, mb.build()))
     }
 
-    pub fn draw(&self, ctx: &mut Context, state: &State, assets: &Assets) -> GameResult {
+    fn draw_heat_map(
+        &self,
+        ctx: &mut Context,
+        temperatures: impl Iterator<Item = (SandboxCoordinate, f32)>,
+        scaling_factor: f32,
+    ) -> GameResult<Mesh> {
+        let mb = &mut MeshBuilder::new();
+        for (coord, temperature) in temperatures {
+            // room temperature is left black, so there's only something to draw if it's
+            // noticeably hotter or colder
+            if (temperature - AMBIENT_TEMPERATURE).abs() < 1f32 {
+                continue;
+            }
+            mb.rectangle(
+                DrawMode::fill(),
+                Rect {
+                    x: coord.x as f32 * scaling_factor,
+                    y: coord.y as f32 * scaling_factor,
+                    w: scaling_factor,
+                    h: scaling_factor,
+                },
+                heat_color(temperature),
+            )
+            .expect("Couldn't draw heat map");
+        }
+        Ok(Mesh::from_data(ctx, mb.build()))
+    }
+
+    fn draw_air_overlay(
+        &self,
+        ctx: &mut Context,
+        air: &Air,
+        scaling_factor: f32,
+    ) -> GameResult<Mesh> {
+        // a translucent square over each air cell, red for high pressure and blue for low, and a
+        // line from its centre showing which way and how hard the wind is blowing
+        let sf = scaling_factor;
+        let size = AIR_CELL_SIZE as f32 * sf;
+        let sandbox = self.frame_sandbox;
+        let mb = &mut MeshBuilder::new();
+        for (coord, pressure, velocity) in air.cells() {
+            let x = coord.x as f32 * sf;
+            let y = coord.y as f32 * sf;
+            // cells along the right and bottom edges can hang over the edge of the sandbox
+            let w = size.min(sandbox.w - 1f32 - x);
+            let h = size.min(sandbox.h - 1f32 - y);
+            let strength = (pressure.abs() / 2f32).min(0.6);
+            if strength > 0.02 {
+                let color = if pressure > 0f32 {
+                    Color::new(1.0, 0.0, 0.0, strength)
+                } else {
+                    Color::new(0.0, 0.3, 1.0, strength)
+                };
+                mb.rectangle(DrawMode::fill(), Rect::new(x, y, w, h), color)?;
+            }
+            if velocity.length() > 0.05 {
+                let centre = Point2::new(x + w / 2f32, y + h / 2f32);
+                let tip = centre + velocity.clamp_length_max(1.0) * size;
+                mb.line(&[centre, tip], 1f32, Color::new(1.0, 1.0, 1.0, 0.7))?;
+            }
+        }
+        Ok(Mesh::from_data(ctx, mb.build()))
+    }
+
+    fn draw_chunk_overlay<'a>(
+        &self,
+        ctx: &mut Context,
+        chunks: impl Iterator<Item = &'a Chunk>,
+        scaling_factor: f32,
+    ) -> GameResult<Option<Mesh>> {
+        // nothing to draw if the whole sandbox is asleep
+        let sf = scaling_factor;
+        let mb = &mut MeshBuilder::new();
+        let mut any = false;
+        for chunk in chunks {
+            mb.rectangle(
+                DrawMode::stroke(1f32),
+                Rect::new(
+                    chunk.origin.x as f32 * sf,
+                    chunk.origin.y as f32 * sf,
+                    chunk.w as f32 * sf,
+                    chunk.h as f32 * sf,
+                ),
+                Color::new(0.0, 1.0, 0.3, 0.6),
+            )?;
+            any = true;
+        }
+        Ok(any.then(|| Mesh::from_data(ctx, mb.build())))
+    }
+
+    fn draw_brush_cursor(&self, ctx: &mut Context, brush: &Brush) -> GameResult<Option<Mesh>> {
+        // outline the area the brush would paint, in sandbox-local pixels
+        let mouse = ctx.mouse.position();
+        if !self.frame_sandbox.contains(mouse) {
+            return Ok(None);
+        }
+        let sf = self.scaling_factor;
+        let cell_x = ((mouse.x - self.frame_sandbox.x) / sf).floor();
+        let cell_y = ((mouse.y - self.frame_sandbox.y) / sf).floor();
+        let r = brush.radius as f32;
+        let side = (2f32 * r + 1f32) * sf;
+        let color = Color::new(1.0, 1.0, 1.0, 0.5);
+        let mb = &mut MeshBuilder::new();
+        match brush.shape {
+            BrushShape::Circle | BrushShape::Spray => mb.circle(
+                DrawMode::stroke(1f32),
+                Point2::new((cell_x + 0.5) * sf, (cell_y + 0.5) * sf),
+                (r + 0.5) * sf,
+                0.5,
+                color,
+            )?,
+            BrushShape::Square => mb.rectangle(
+                DrawMode::stroke(1f32),
+                Rect::new((cell_x - r) * sf, (cell_y - r) * sf, side, side),
+                color,
+            )?,
+            BrushShape::Line => mb.rectangle(
+                DrawMode::stroke(1f32),
+                Rect::new((cell_x - r) * sf, cell_y * sf, side, sf),
+                color,
+            )?,
+        };
+        Ok(Some(Mesh::from_data(ctx, mb.build())))
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        state: &State,
+        timing: &Timing,
+        _assets: &Assets,
+    ) -> GameResult {
         // refresh screen
         let mut canvas = Canvas::from_frame(ctx, Color::BLACK);
 
         // all drawing steps here
-        let atoms_m = self.draw_atoms(ctx, state.get_atoms(), self.get_scaling_factor() as i32)?;
-        let fps = self.draw_fps(ctx, self.frame_fps, &self.font)?;
+        let atoms_m = match self.view {
+            View::Elements => self.draw_atoms(ctx, state.atoms(), self.get_scaling_factor())?,
+            View::Heat => {
+                self.draw_heat_map(ctx, state.temperatures(), self.get_scaling_factor())?
+            }
+        };
+        let air_m = if self.show_air {
+            Some(self.draw_air_overlay(ctx, state.air(), self.get_scaling_factor())?)
+        } else {
+            None
+        };
+        let chunks_m = if self.show_chunks {
+            self.draw_chunk_overlay(ctx, state.active_chunks(), self.get_scaling_factor())?
+        } else {
+            None
+        };
+        let fps = self.draw_fps(
+            ctx,
+            self.frame_fps,
+            &self.font,
+            timing,
+            &state.parameters.gravity,
+        )?;
+        let brush_cursor = self.draw_brush_cursor(ctx, &state.brush)?;
         canvas.draw(
             &self.mesh_sandbox.clone().unwrap(),
             DrawParam::default().dest(Point2::new(self.frame_sandbox.x, self.frame_sandbox.y)),
@@ -242,6 +504,24 @@ impl Renderer {
             &atoms_m,
             DrawParam::default().dest(Point2::new(self.frame_sandbox.x, self.frame_sandbox.y)),
         );
+        if let Some(air_m) = air_m {
+            canvas.draw(
+                &air_m,
+                DrawParam::default().dest(Point2::new(self.frame_sandbox.x, self.frame_sandbox.y)),
+            );
+        }
+        if let Some(chunks_m) = chunks_m {
+            canvas.draw(
+                &chunks_m,
+                DrawParam::default().dest(Point2::new(self.frame_sandbox.x, self.frame_sandbox.y)),
+            );
+        }
+        if let Some(brush_cursor) = brush_cursor {
+            canvas.draw(
+                &brush_cursor,
+                DrawParam::default().dest(Point2::new(self.frame_sandbox.x, self.frame_sandbox.y)),
+            );
+        }
         canvas.draw(
             &fps,
             DrawParam::default().dest(Point2::new(self.frame_fps.x, self.frame_fps.y)),
@@ -253,7 +533,7 @@ impl Renderer {
             );
             canvas.draw(
                 &button.text,
-                DrawParam::default().dest(Point2::new(button.rect.x + 7f32, button.rect.y + 7f32)),
+                DrawParam::default().dest(Point2::new(button.rect.x + 6f32, button.rect.y + 6f32)),
             );
         }
         // output drawing
@@ -261,3 +541,20 @@ impl Renderer {
         Ok(())
     }
 }
+
+fn heat_color(temperature: f32) -> Color {
+    // black at room temperature, bluer the colder it gets, and through red and yellow to white
+    // the hotter it gets
+    let warmth = temperature - AMBIENT_TEMPERATURE;
+    if warmth < 0f32 {
+        Color::new(0.0, 0.0, (-warmth / 50f32).min(1.0), 1.0)
+    } else {
+        let heat = warmth / 400f32;
+        Color::new(
+            heat.min(1.0),
+            (heat - 1f32).clamp(0.0, 1.0),
+            (heat - 2f32).clamp(0.0, 1.0),
+            1.0,
+        )
+    }
+}
@@ -0,0 +1,343 @@
+// Air. The sandbox is covered by a coarse grid of air cells, each AIR_CELL_SIZE atoms across,
+// with a pressure and a velocity. Every tick air is pushed from high pressure towards low,
+// pressure builds up wherever air converges, the wind carries itself along, and both die away
+// slowly. Solid atoms block air cells that are mostly full of them, and the edges of the sandbox
+// are open, so pressure leaks out of them. Only the air over awake chunks, and just round them, is
+// updated, and anywhere it's stirred up wakes the chunks under it.
+use glam::Vec2;
+use rand::Rng;
+
+use super::atom::Element;
+use super::cells::Cells;
+use super::registry::MatterState;
+use super::sweep::{Chunk, DirtyRect};
+use super::SandboxCoordinate;
+
+pub const AIR_CELL_SIZE: i32 = 4;
+// how quickly a difference in pressure gets air moving, and moving air builds up pressure
+const PRESSURE_TO_VELOCITY: f32 = 0.1;
+const VELOCITY_TO_PRESSURE: f32 = 0.1;
+// how much of its pressure and velocity an air cell keeps from one tick to the next
+const PRESSURE_DECAY: f32 = 0.98;
+const VELOCITY_DECAY: f32 = 0.95;
+// fastest the wind can blow, in air cells per tick
+const MAX_VELOCITY: f32 = 2.0;
+// wind slower than this is too gentle to push anything
+const CALM: f32 = 0.05;
+// a change in pressure smaller than this in a tick, with the wind calm, lets the chunks under an
+// air cell sleep
+const WAKING_CHANGE: f32 = 0.001;
+
+fn susceptibility(element: Element) -> f32 {
+    // how easily the wind pushes an element about; liquids and solids are too heavy to notice
+    match element.properties().state {
+        MatterState::Gas => 1.0,
+        MatterState::Powder => 0.25,
+        MatterState::Liquid | MatterState::Solid => 0.0,
+    }
+}
+
+fn shove(speed: f32, rng: &mut impl Rng) -> i32 {
+    // one cell in the direction of speed, with a chance of speed
+    if rng.gen::<f32>() < speed.abs() {
+        speed.signum() as i32
+    } else {
+        0
+    }
+}
+
+#[derive(Clone)]
+pub struct Air {
+    size: (i32, i32),
+    pressure: Vec<f32>,
+    velocity: Vec<Vec2>,
+    // air cells mostly full of solids, which air can't flow through
+    blocked: Vec<bool>,
+}
+
+impl Air {
+    pub fn new(sandbox_w: i32, sandbox_h: i32) -> Self {
+        // enough air cells to cover the sandbox, even if it isn't a multiple of the cell size
+        let w = (sandbox_w + AIR_CELL_SIZE - 1) / AIR_CELL_SIZE;
+        let h = (sandbox_h + AIR_CELL_SIZE - 1) / AIR_CELL_SIZE;
+        Air {
+            size: (w, h),
+            pressure: vec![0.0; (w * h) as usize],
+            velocity: vec![Vec2::ZERO; (w * h) as usize],
+            blocked: vec![false; (w * h) as usize],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.size.0 + x) as usize
+    }
+
+    fn index_of(&self, coord: SandboxCoordinate) -> usize {
+        self.index(coord.x / AIR_CELL_SIZE, coord.y / AIR_CELL_SIZE)
+    }
+
+    pub fn velocity_at(&self, coord: SandboxCoordinate) -> Vec2 {
+        self.velocity[self.index_of(coord)]
+    }
+
+    pub fn add_pressure(&mut self, coord: SandboxCoordinate, pressure: f32) {
+        let index = self.index_of(coord);
+        self.pressure[index] += pressure;
+    }
+
+    pub fn add_velocity(&mut self, coord: SandboxCoordinate, velocity: Vec2) {
+        let index = self.index_of(coord);
+        self.velocity[index] = (self.velocity[index] + velocity).clamp_length_max(MAX_VELOCITY);
+    }
+
+    pub fn set(&mut self, coord: SandboxCoordinate, pressure: f32, velocity: Vec2) {
+        let index = self.index_of(coord);
+        self.pressure[index] = pressure;
+        self.velocity[index] = velocity;
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (SandboxCoordinate, f32, Vec2)> + '_ {
+        // the top left corner of each air cell in the sandbox, with its pressure and velocity
+        (0..self.pressure.len()).map(move |i| {
+            let (x, y) = (i as i32 % self.size.0, i as i32 / self.size.0);
+            (
+                SandboxCoordinate {
+                    x: x * AIR_CELL_SIZE,
+                    y: y * AIR_CELL_SIZE,
+                },
+                self.pressure[i],
+                self.velocity[i],
+            )
+        })
+    }
+
+    pub fn push(
+        &self,
+        element: Element,
+        coord: SandboxCoordinate,
+        rng: &mut impl Rng,
+    ) -> (i32, i32) {
+        // how far the wind shoves an atom this tick: the faster the wind, the more likely it is
+        // to move the atom a cell along each axis
+        let wind = self.velocity_at(coord) * susceptibility(element);
+        if wind.length() < CALM {
+            return (0, 0);
+        }
+        (shove(wind.x, rng), shove(wind.y, rng))
+    }
+
+    fn active(&self, chunks: &[Chunk], awake: &[bool]) -> Vec<usize> {
+        // the air cells over awake chunks, and the ones just round them
+        let (w, h) = self.size;
+        let mut active = vec![false; self.pressure.len()];
+        for chunk in chunks.iter().filter(|chunk| awake[chunk.index]) {
+            let (x0, y0) = (
+                (chunk.origin.x / AIR_CELL_SIZE - 1).max(0),
+                (chunk.origin.y / AIR_CELL_SIZE - 1).max(0),
+            );
+            let (x1, y1) = (
+                ((chunk.origin.x + chunk.w - 1) / AIR_CELL_SIZE + 1).min(w - 1),
+                ((chunk.origin.y + chunk.h - 1) / AIR_CELL_SIZE + 1).min(h - 1),
+            );
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    active[self.index(x, y)] = true;
+                }
+            }
+        }
+        (0..active.len()).filter(|&i| active[i]).collect()
+    }
+
+    fn update_blocked(&mut self, cells: &Cells, sandbox_w: i32, sandbox_h: i32, active: &[usize]) {
+        for &i in active {
+            // cells on the right and bottom edges can be cut short by the edge of the sandbox
+            let (x, y) = (
+                i as i32 % self.size.0 * AIR_CELL_SIZE,
+                i as i32 / self.size.0 * AIR_CELL_SIZE,
+            );
+            let (w, h) = (
+                AIR_CELL_SIZE.min(sandbox_w - x),
+                AIR_CELL_SIZE.min(sandbox_h - y),
+            );
+            let solids = (y..y + h)
+                .flat_map(|y| (x..x + w).map(move |x| SandboxCoordinate { x, y }))
+                .filter(|&coord| {
+                    cells
+                        .get_cell_contents(coord)
+                        .is_some_and(|atom| atom.element().is_solid())
+                })
+                .count() as i32;
+            self.blocked[i] = solids * 2 > w * h;
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        cells: &Cells,
+        sandbox_w: i32,
+        sandbox_h: i32,
+        chunks: &[Chunk],
+        awake: &[bool],
+    ) -> Vec<DirtyRect> {
+        // returns a rectangle around each air cell that was stirred up enough to wake the atoms
+        // under it
+        let active = self.active(chunks, awake);
+        self.update_blocked(cells, sandbox_w, sandbox_h, &active);
+        let (w, h) = self.size;
+        // beyond the edges is still air, at rest; a blocked cell looks like more of whatever
+        // it's next to, so nothing flows into it
+        let pressure = |air: &Air, x: i32, y: i32, own: f32| {
+            if x < 0 || y < 0 || x >= w || y >= h {
+                0.0
+            } else if air.blocked[air.index(x, y)] {
+                own
+            } else {
+                air.pressure[air.index(x, y)]
+            }
+        };
+        let velocity = |air: &Air, x: i32, y: i32| {
+            if x < 0 || y < 0 || x >= w || y >= h || air.blocked[air.index(x, y)] {
+                Vec2::ZERO
+            } else {
+                air.velocity[air.index(x, y)]
+            }
+        };
+        let position = |i: usize| (i as i32 % w, i as i32 / w);
+
+        // first the wind carries itself along, each cell taking on the velocity of the air that's
+        // blowing into it
+        let velocities: Vec<_> = active
+            .iter()
+            .map(|&i| {
+                let (x, y) = position(i);
+                if self.blocked[i] {
+                    return self.velocity[i];
+                }
+                // blending the four air cells around wherever that air came from, and taking
+                // anything from beyond the edges as coming from the edge itself
+                let from = (Vec2::new(x as f32, y as f32) - self.velocity[i])
+                    .clamp(Vec2::ZERO, Vec2::new((w - 1) as f32, (h - 1) as f32));
+                let (from_x, from_y) = (from.x.floor() as i32, from.y.floor() as i32);
+                let (tx, ty) = (from.x - from_x as f32, from.y - from_y as f32);
+                let carried = velocity(self, from_x, from_y) * (1.0 - tx) * (1.0 - ty)
+                    + velocity(self, from_x + 1, from_y) * tx * (1.0 - ty)
+                    + velocity(self, from_x, from_y + 1) * (1.0 - tx) * ty
+                    + velocity(self, from_x + 1, from_y + 1) * tx * ty;
+                (carried * VELOCITY_DECAY).clamp_length_max(MAX_VELOCITY)
+            })
+            .collect();
+        for (&i, velocity) in active.iter().zip(velocities) {
+            self.velocity[i] = velocity;
+        }
+
+        // then air accelerates down the pressure gradient
+        let velocities: Vec<_> = active
+            .iter()
+            .map(|&i| {
+                let (x, y) = position(i);
+                if self.blocked[i] {
+                    return Vec2::ZERO;
+                }
+                let own = self.pressure[i];
+                let gradient = Vec2::new(
+                    pressure(self, x + 1, y, own) - pressure(self, x - 1, y, own),
+                    pressure(self, x, y + 1, own) - pressure(self, x, y - 1, own),
+                );
+                (self.velocity[i] - gradient * PRESSURE_TO_VELOCITY).clamp_length_max(MAX_VELOCITY)
+            })
+            .collect();
+        for (&i, velocity) in active.iter().zip(velocities) {
+            self.velocity[i] = velocity;
+        }
+
+        // and piles up wherever it converges
+        let pressures: Vec<_> = active
+            .iter()
+            .map(|&i| {
+                let (x, y) = position(i);
+                if self.blocked[i] {
+                    return self.pressure[i];
+                }
+                let divergence = velocity(self, x + 1, y).x - velocity(self, x - 1, y).x
+                    + velocity(self, x, y + 1).y
+                    - velocity(self, x, y - 1).y;
+                (self.pressure[i] - divergence * VELOCITY_TO_PRESSURE) * PRESSURE_DECAY
+            })
+            .collect();
+        let mut stirred = vec![];
+        for (&i, pressure) in active.iter().zip(pressures) {
+            let change = (pressure - self.pressure[i]).abs();
+            self.pressure[i] = pressure;
+            if change > WAKING_CHANGE || self.velocity[i].length() >= CALM {
+                let (x, y) = position(i);
+                let min = SandboxCoordinate {
+                    x: x * AIR_CELL_SIZE,
+                    y: y * AIR_CELL_SIZE,
+                };
+                let max = SandboxCoordinate {
+                    x: (min.x + AIR_CELL_SIZE).min(sandbox_w) - 1,
+                    y: (min.y + AIR_CELL_SIZE).min(sandbox_h) - 1,
+                };
+                stirred.push(DirtyRect { min, max });
+            }
+        }
+        stirred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_pressure(air: &Air) -> f32 {
+        air.pressure.iter().sum()
+    }
+
+    #[test]
+    fn pressure_spreads_out_and_dies_away() {
+        let mut air = Air::new(40, 40);
+        let cells = Cells::new(40, 40);
+        let (chunks, awake) = (super::super::sweep::chunks(40, 40), [true; 4]);
+        let middle = SandboxCoordinate { x: 20, y: 20 };
+        air.add_pressure(middle, 10.0);
+        air.update(&cells, 40, 40, &chunks, &awake);
+        // the air rushes outwards from the middle
+        let right = SandboxCoordinate { x: 24, y: 20 };
+        assert!(air.velocity_at(right).x > 0.0);
+        assert!(air.velocity_at(SandboxCoordinate { x: 16, y: 20 }).x < 0.0);
+        for _ in 0..500 {
+            air.update(&cells, 40, 40, &chunks, &awake);
+        }
+        assert!(total_pressure(&air).abs() < 0.1);
+        assert!(air.velocity.iter().all(|v| v.length() < 0.01));
+    }
+
+    #[test]
+    fn solids_block_the_air() {
+        // a wall down the middle of the sandbox keeps pressure on one side from pushing air
+        // through to the other
+        let mut cells = Cells::new(12, 4);
+        let wall = super::super::Element::named("wall").unwrap();
+        for y in 0..4 {
+            for x in 4..8 {
+                cells
+                    .fill_cell(SandboxCoordinate { x, y }, super::super::Atom::new(wall, 0))
+                    .unwrap();
+            }
+        }
+        let mut air = Air::new(12, 4);
+        let chunks = super::super::sweep::chunks(12, 4);
+        air.add_pressure(SandboxCoordinate { x: 0, y: 0 }, 10.0);
+        for _ in 0..10 {
+            air.update(&cells, 12, 4, &chunks, &[true]);
+        }
+        assert_eq!(
+            air.velocity_at(SandboxCoordinate { x: 5, y: 0 }),
+            Vec2::ZERO
+        );
+        assert_eq!(
+            air.velocity_at(SandboxCoordinate { x: 9, y: 0 }),
+            Vec2::ZERO
+        );
+    }
+}
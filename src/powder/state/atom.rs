@@ -1,77 +1,201 @@
+use std::ops::RangeInclusive;
+
 use log::debug;
 
 use ggez::graphics::Color;
-use strum_macros::EnumIter;
+use glam::Vec2;
+use rand::Rng;
+
+use super::neighbourhood::{Neighbour, Neighbourhood};
+use super::registry::{ElementProperties, ElementRegistry, MatterState};
 
-use super::SandboxCoordinate;
+// how much faster a falling atom gets every tick, in cells per tick, up to terminal velocity
+const GRAVITY: f32 = 0.2;
+const TERMINAL_VELOCITY: f32 = 8.0;
+// how much of its speed an atom throws out sideways when it lands
+const SPLASH: f32 = 0.5;
+// how much of its sideways speed an atom keeps from one tick to the next as it slides along
+const FRICTION: f32 = 0.8;
 
-fn heads_or_tails() -> i32 {
+fn heads_or_tails(rng: &mut impl Rng) -> i32 {
     // Returns -1 or +1.
-    if rand::random::<bool>() {
+    if rng.gen::<bool>() {
         1
     } else {
         -1
     }
 }
 
-fn heads_or_zip() -> i32 {
-    // Returns -1 or +1.
-    if rand::random::<bool>() {
-        1
-    } else {
-        0
-    }
-}
+// An element is just its index into the registry, which holds everything about how it looks and
+// behaves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Element(u8);
 
-#[derive(Copy, Clone, Debug, EnumIter)]
-pub enum Element {
-    Sand,
-    Water,
+impl std::fmt::Display for Element {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.properties().name)
+    }
 }
 
 impl Element {
-    pub fn to_string(&self) -> String {
-        match self {
-            Element::Sand => "sand".to_string(),
-            Element::Water => "water".to_string(),
-        }
+    pub(super) fn from_id(id: u8) -> Self {
+        Element(id)
+    }
+
+    pub fn id(&self) -> u8 {
+        self.0
+    }
+
+    pub fn named(name: &str) -> Option<Element> {
+        ElementRegistry::global().find(name)
+    }
+
+    pub fn properties(&self) -> &'static ElementProperties {
+        ElementRegistry::global().get(*self)
     }
 
     fn color(&self) -> Color {
-        match self {
-            Element::Sand => Color::WHITE,
-            Element::Water => Color::BLUE,
+        self.properties().color
+    }
+
+    pub fn transition(&self, temperature: f32) -> Option<Element> {
+        // what this element turns into at temperature, if it's past one of its thresholds
+        let properties = self.properties();
+        properties
+            .heats_into
+            .filter(|transition| temperature > transition.temperature)
+            .or_else(|| {
+                properties
+                    .cools_into
+                    .filter(|transition| temperature < transition.temperature)
+            })
+            .map(|transition| transition.element)
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.properties().state == MatterState::Solid
+    }
+
+    pub fn is_gas(&self) -> bool {
+        self.properties().state == MatterState::Gas
+    }
+
+    pub fn can_displace(&self, neighbour: Neighbour) -> bool {
+        // whether this element could move into the neighbouring cell, swapping with whatever
+        // is there
+        match neighbour {
+            Neighbour::Empty => true,
+            Neighbour::Atom(other) if other.is_solid() => false,
+            Neighbour::Atom(other) => other.properties().density < self.properties().density,
+            Neighbour::OutOfBounds => false,
+        }
+    }
+
+    pub fn can_rise_into(&self, neighbour: Neighbour) -> bool {
+        // the upwards version of can_displace, which only gases ever do: a gas rises through a
+        // heavier gas by swapping places with it. anything denser than a gas sinks through it on
+        // its own, so there's no need for gases to push their way up through anything else
+        if !self.is_gas() {
+            return false;
+        }
+        match neighbour {
+            Neighbour::Empty => true,
+            Neighbour::Atom(other) => {
+                other.is_gas() && other.properties().density > self.properties().density
+            }
+            Neighbour::OutOfBounds => false,
         }
     }
 
-    fn calculate_move(&self, neighbourhood: Vec<bool>) -> (i32, i32) {
-        let (dx, dy) = match self {
-            Element::Sand => {
-                match neighbourhood[..] {
-                    [_, _, _, _, _, _, false, _] => (0, 1),
-                    [_, _, _, _, _, false, true, true] => (-1, 1),
-                    [_, _, _, _, _, true, true, false] => (1, 1),
-                    [_, _, _, _, _, false, true, false] => (heads_or_tails(), 1),
-                    // yeah all the other coords are unused for now but could be useful later
-                    _ => (0, 0),
-                }
+    pub fn could_move(&self, nh: &Neighbourhood) -> bool {
+        // a cheap check for whether there's anywhere this element could move to at all
+        let free_below = nh
+            .sides_and_below()
+            .iter()
+            .any(|&neighbour| self.can_displace(neighbour));
+        free_below
+            || nh
+                .above_row()
+                .iter()
+                .any(|&neighbour| self.can_rise_into(neighbour))
+    }
+
+    fn calculate_drift(&self, nh: &Neighbourhood, rng: &mut impl Rng) -> (i32, i32) {
+        // gases rise, drifting from side to side as they go. once they can't rise any further
+        // they wander about at random, sometimes downwards, so a trapped gas spreads out to fill
+        // whatever it's trapped in
+        let drift = rng.gen_range(-1..=1);
+        let above = [nh.above_left(), nh.above(), nh.above_right()];
+        if self.can_rise_into(above[(drift + 1) as usize]) {
+            (drift, -1)
+        } else if self.can_rise_into(nh.above()) {
+            (0, -1)
+        } else {
+            self.wander(0..=1, rng)
+        }
+    }
+
+    fn wander(&self, vertical: RangeInclusive<i32>, rng: &mut impl Rng) -> (i32, i32) {
+        let dispersion = self.properties().dispersion;
+        (
+            heads_or_tails(rng) * rng.gen_range(1..=dispersion),
+            rng.gen_range(vertical),
+        )
+    }
+
+    fn calculate_move(
+        &self,
+        nh: &Neighbourhood,
+        weightless: bool,
+        rng: &mut impl Rng,
+    ) -> (i32, i32) {
+        // returns which way to try to move, and how far; a liquid's sideways flow can be more
+        // than one cell, and it's up to the caller to stop it at the first obstacle. falls are
+        // always one cell here, and it's up to the atom's velocity how far it actually falls.
+        // down is whichever way gravity pulls, and it's up to the caller to turn the move round
+        // to match
+        let properties = self.properties();
+        if weightless {
+            // with nothing pulling them anywhere, gases wander about, as likely to go one way
+            // as the other, and everything else stays where it is
+            return match properties.state {
+                MatterState::Gas => self.wander(-1..=1, rng),
+                _ => (0, 0),
+            };
+        }
+        if properties.state == MatterState::Gas {
+            return self.calculate_drift(nh, rng);
+        }
+        let free = |neighbour| self.can_displace(neighbour);
+        let fall = if free(nh.below()) {
+            Some((0, 1))
+        } else {
+            match (free(nh.below_left()), free(nh.below_right())) {
+                (true, false) => Some((-1, 1)),
+                (false, true) => Some((1, 1)),
+                (true, true) => Some((heads_or_tails(rng), 1)),
+                (false, false) => None,
             }
-            Element::Water => {
-                match neighbourhood[..] {
-                    [_, _, _, _, _, _, false, _] => (0, 1),
-                    [_, _, _, _, _, false, true, true] => (-1, 1),
-                    [_, _, _, _, _, true, true, false] => (1, 1),
-                    [_, _, _, _, _, false, true, false] => (heads_or_tails(), 1),
-                    // for remaining cases we can assume coords below are full
-                    [_, _, _, true, false, _, _, _] => (heads_or_zip(), 0),
-                    [_, _, _, false, true, _, _, _] => (heads_or_zip() * -1, 0),
-                    [_, _, _, false, false, _, _, _] => (heads_or_tails(), 0),
-                    // yeah all the other coords are unused for now but could be useful later
-                    _ => (0, 0),
-                }
+        };
+        let (dx, dy) = match (fall, properties.state) {
+            (_, MatterState::Solid) | (_, MatterState::Gas) => (0, 0),
+            (Some((0, dy)), _) => (0, dy),
+            (Some(fall), MatterState::Powder) => fall,
+            (None, MatterState::Powder) => (0, 0),
+            // any sideways movement of a liquid is held back by its viscosity
+            (_, MatterState::Liquid) if rng.gen::<f32>() < properties.viscosity => (0, 0),
+            (Some(fall), MatterState::Liquid) => fall,
+            (None, MatterState::Liquid) => {
+                // for remaining cases we can assume coords below are full
+                let direction = match (free(nh.left()), free(nh.right())) {
+                    (true, false) => -1,
+                    (false, true) => 1,
+                    (true, true) => heads_or_tails(rng),
+                    (false, false) => 0,
+                };
+                (direction * rng.gen_range(1..=properties.dispersion), 0)
             }
         };
-        assert!((-1..2).contains(&dx));
         assert!((-1..2).contains(&dy));
         (dx, dy)
     }
@@ -80,40 +204,96 @@ impl Element {
 #[derive(Copy, Clone, Debug)]
 pub struct Atom {
     element: Element,
-    pub coord: SandboxCoordinate,
-    pub next_coord: SandboxCoordinate,
+    // an atom's position is wherever it sits in Cells, so all it needs to carry is which tick it
+    // was last updated on, so that atoms moving ahead of the sweep aren't updated twice
+    pub clock: u32,
+    // ticks left until the atom disappears, for elements that don't last forever
+    pub lifetime: Option<u16>,
+    // ticks left until the atom burns out, if it's alight
+    pub burning: Option<u16>,
+    // in cells per tick, relative to gravity, so positive y is the way gravity pulls; only
+    // powders and liquids pick up speed, by falling, and they lose it again by landing on
+    // something
+    pub velocity: Vec2,
 }
 
 impl Atom {
-    pub fn new(coord: SandboxCoordinate, element: Element) -> Self {
+    pub fn new(element: Element, clock: u32) -> Self {
         Atom {
-            coord: coord,
-            element: element,
-            next_coord: coord,
+            element,
+            clock,
+            lifetime: element.properties().lifetime,
+            burning: None,
+            velocity: Vec2::ZERO,
         }
     }
 
+    pub fn element(&self) -> Element {
+        self.element
+    }
+
     pub fn color(&self) -> Color {
-        self.element.color()
+        // atoms that are alight glow orange
+        let color = self.element.color();
+        match self.burning {
+            Some(_) => Color::new(
+                (color.r + 1.0) / 2.0,
+                (color.g + 0.5) / 2.0,
+                color.b / 2.0,
+                color.a,
+            ),
+            None => color,
+        }
+    }
+
+    pub fn is_alight(&self) -> bool {
+        self.burning.is_some() || self.element.properties().ignites
+    }
+
+    pub fn fall(&mut self, gravity: f32) -> i32 {
+        // picks up speed while falling, faster the stronger gravity is; returns how many cells
+        // to fall this tick, which is always at least one
+        self.velocity = Vec2::new(
+            0.0,
+            (self.velocity.y + GRAVITY * gravity).min(TERMINAL_VELOCITY),
+        );
+        (self.velocity.y.floor() as i32).max(1)
+    }
+
+    pub fn land(&mut self, rng: &mut impl Rng) {
+        // hitting something throws some of a falling atom's speed out to one side or the other,
+        // so that a fast enough fall splashes; a gentle one just stops
+        let splash = self.velocity.y * SPLASH;
+        self.velocity = if splash >= 1.0 {
+            Vec2::new(heads_or_tails(rng) as f32 * splash, 0.0)
+        } else {
+            Vec2::ZERO
+        };
     }
 
-    pub fn set_next(&mut self, neighbourhood: Vec<bool>) {
-        // neighbourhood is a vec of surrounding coords in 1,2,3,4,6,7,8,9 order
-        // as on a keypad (as if the updating atom is at pos 5), where the
-        // element is true if that coord contains an atom and false if it
-        // doesn't
-        assert_eq!(neighbourhood.len(), 8);
-        let (dx, dy) = self.element.calculate_move(neighbourhood);
-        self.next_coord.x = self.coord.x + dx;
-        self.next_coord.y = self.coord.y + dy;
-        debug!("{self:?}");
+    pub fn slide(&mut self) -> i32 {
+        // how far a landed atom slides sideways this tick, slowing down as it goes
+        let slide = self.velocity.x.round() as i32;
+        self.velocity.x *= FRICTION;
+        if self.velocity.x.abs() < 0.5 {
+            self.velocity.x = 0.0;
+        }
+        self.velocity.y = 0.0;
+        slide
     }
 
-    pub fn reset_next(&mut self) {
-        self.next_coord = self.coord;
+    pub fn stop(&mut self) {
+        self.velocity = Vec2::ZERO;
     }
 
-    pub fn update(&mut self) {
-        self.coord = self.next_coord;
+    pub fn calculate_move(
+        &self,
+        neighbourhood: &Neighbourhood,
+        weightless: bool,
+        rng: &mut impl Rng,
+    ) -> (i32, i32) {
+        let (dx, dy) = self.element.calculate_move(neighbourhood, weightless, rng);
+        debug!("{self:?} moving ({dx}, {dy})");
+        (dx, dy)
     }
 }
@@ -0,0 +1,148 @@
+use rand::Rng;
+
+use super::SandboxCoordinate;
+
+const MAX_BRUSH_RADIUS: i32 = 20;
+// fraction of the brush area that the spray fills each frame
+const SPRAY_DENSITY: f64 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Circle,
+    Square,
+    Line,
+    Spray,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Brush {
+    pub radius: i32,
+    pub shape: BrushShape,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            radius: 2,
+            shape: BrushShape::Circle,
+        }
+    }
+}
+
+impl Brush {
+    pub fn resize(&mut self, delta: i32) {
+        self.radius = (self.radius + delta).clamp(0, MAX_BRUSH_RADIUS);
+    }
+
+    pub fn next_shape(&mut self) {
+        self.shape = match self.shape {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::Line,
+            BrushShape::Line => BrushShape::Spray,
+            BrushShape::Spray => BrushShape::Circle,
+        };
+    }
+
+    fn in_circle(&self, dx: i32, dy: i32) -> bool {
+        dx * dx + dy * dy <= self.radius * self.radius
+    }
+
+    pub fn cells(&self, centre: SandboxCoordinate, rng: &mut impl Rng) -> Vec<SandboxCoordinate> {
+        // every cell the brush covers when centred on centre, which may include out of bounds
+        // cells; it's up to the caller to skip those
+        let r = self.radius;
+        let mut cells = vec![];
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let covered = match self.shape {
+                    BrushShape::Circle => self.in_circle(dx, dy),
+                    BrushShape::Square => true,
+                    BrushShape::Line => dy == 0,
+                    BrushShape::Spray => {
+                        (dx == 0 && dy == 0)
+                            || (self.in_circle(dx, dy) && rng.gen::<f64>() < SPRAY_DENSITY)
+                    }
+                };
+                if covered {
+                    cells.push(SandboxCoordinate {
+                        x: centre.x + dx,
+                        y: centre.y + dy,
+                    });
+                }
+            }
+        }
+        cells
+    }
+}
+
+pub fn line(from: SandboxCoordinate, to: SandboxCoordinate) -> Vec<SandboxCoordinate> {
+    // Bresenham's line from from to to, inclusive of both ends
+    let (dx, dy) = ((to.x - from.x).abs(), -(to.y - from.y).abs());
+    let (step_x, step_y) = ((to.x - from.x).signum(), (to.y - from.y).signum());
+    let mut err = dx + dy;
+    let mut coord = from;
+    let mut cells = vec![coord];
+    while coord != to {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            coord.x += step_x;
+        }
+        if e2 <= dx {
+            err += dx;
+            coord.y += step_y;
+        }
+        cells.push(coord);
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTRE: SandboxCoordinate = SandboxCoordinate { x: 5, y: 5 };
+
+    fn cells(brush: &Brush, centre: SandboxCoordinate) -> Vec<SandboxCoordinate> {
+        brush.cells(centre, &mut rand::thread_rng())
+    }
+
+    #[test]
+    fn brush_shapes_cover_expected_cells() {
+        let mut brush = Brush {
+            radius: 1,
+            shape: BrushShape::Square,
+        };
+        assert_eq!(cells(&brush, CENTRE).len(), 9);
+        brush.shape = BrushShape::Line;
+        assert_eq!(cells(&brush, CENTRE).len(), 3);
+        brush.shape = BrushShape::Circle;
+        assert_eq!(cells(&brush, CENTRE).len(), 5);
+        brush.radius = 0;
+        assert_eq!(cells(&brush, CENTRE), vec![CENTRE]);
+    }
+
+    #[test]
+    fn brush_radius_is_clamped() {
+        let mut brush = Brush::default();
+        brush.resize(-100);
+        assert_eq!(brush.radius, 0);
+        brush.resize(100);
+        assert_eq!(brush.radius, MAX_BRUSH_RADIUS);
+    }
+
+    #[test]
+    fn line_has_no_gaps() {
+        let to = SandboxCoordinate { x: 12, y: -3 };
+        let cells = line(CENTRE, to);
+        assert_eq!(cells.first(), Some(&CENTRE));
+        assert_eq!(cells.last(), Some(&to));
+        // one cell per step along the major axis, each touching the last
+        assert_eq!(cells.len(), 9);
+        for pair in cells.windows(2) {
+            assert!((pair[1].x - pair[0].x).abs() <= 1);
+            assert!((pair[1].y - pair[0].y).abs() <= 1);
+        }
+        assert_eq!(line(CENTRE, CENTRE), vec![CENTRE]);
+    }
+}
@@ -1,6 +1,9 @@
+use std::ops::Range;
+
 use anyhow::Result;
 use thiserror::Error;
 
+use super::heat::AMBIENT_TEMPERATURE;
 use super::Atom;
 use super::SandboxCoordinate;
 
@@ -10,63 +13,157 @@ pub enum CellsError {
     CouldNotFillCell { x: i32, y: i32 },
 }
 
+// what cells can be kept in: vectors of their own, or slices borrowed from a bigger set of cells
+pub trait Storage<T>: AsRef<[T]> + AsMut<[T]> {}
+impl<T, S: AsRef<[T]> + AsMut<[T]>> Storage<T> for S {}
+
 #[derive(Clone)]
-pub struct Cells {
+pub struct Cells<A = Vec<Option<Atom>>, T = Vec<f32>> {
+    // where the top left cell is in the sandbox, which is only anywhere other than (0,0) for a
+    // band of rows borrowed from the whole thing
+    origin: SandboxCoordinate,
     size: (i32, i32),
-    array: Vec<Option<Atom>>,
+    array: A,
+    // laid out the same as array; every cell has a temperature, empty or not
+    temperatures: T,
 }
 
+// a band of whole rows of the sandbox, borrowed from its cells
+pub type CellsMut<'a> = Cells<&'a mut [Option<Atom>], &'a mut [f32]>;
+
 // Cells keeps a vector of every cell in the drawable space, starting from
 // (0,0)..(max_width,0) then continuing on to (0,1)..(max_width,1), all the way
 // up to (max_width,max_height), so the overall array is max_width * max_height
-// * cellsize in memory.
+// * cellsize in memory. It's the only record of where atoms are; callers are
+// expected to bounds check coords before handing them over. Alongside it is the
+// temperature of every cell, which moves with the atoms as they're swapped about.
+// Bands of rows that don't overlap can be borrowed all at once, and updated on separate threads
+// with the same coords as the whole thing, without copying anything.
 impl Cells {
-    pub fn new(sandbox_size: i32) -> Self {
+    pub fn new(sandbox_w: i32, sandbox_h: i32) -> Self {
+        Cells {
+            origin: SandboxCoordinate { x: 0, y: 0 },
+            size: (sandbox_w, sandbox_h),
+            array: vec![None; (sandbox_w * sandbox_h) as usize],
+            temperatures: vec![AMBIENT_TEMPERATURE; (sandbox_w * sandbox_h) as usize],
+        }
+    }
+
+    pub fn rows_mut(&mut self) -> CellsMut<'_> {
+        // every row, as one band
         Cells {
-            size: (sandbox_size, sandbox_size),
-            array: vec![None; (sandbox_size * sandbox_size) as usize],
+            origin: self.origin,
+            size: self.size,
+            array: &mut self.array,
+            temperatures: &mut self.temperatures,
+        }
+    }
+
+    pub fn bands_mut(&mut self, bands: &[Range<i32>]) -> Vec<CellsMut<'_>> {
+        // borrows every band of rows at once; they have to run down the sandbox in order,
+        // without overlapping
+        let w = self.size.0;
+        let mut array = &mut self.array[..];
+        let mut temperatures = &mut self.temperatures[..];
+        let mut y = 0;
+        let mut borrowed = vec![];
+        for band in bands {
+            assert!(y <= band.start && band.start <= band.end && band.end <= self.size.1);
+            let skip = ((band.start - y) * w) as usize;
+            let len = ((band.end - band.start) * w) as usize;
+            let (band_array, rest) = array[skip..].split_at_mut(len);
+            array = rest;
+            let (band_temperatures, rest) = temperatures[skip..].split_at_mut(len);
+            temperatures = rest;
+            borrowed.push(Cells {
+                origin: SandboxCoordinate {
+                    x: 0,
+                    y: band.start,
+                },
+                size: (w, band.end - band.start),
+                array: band_array,
+                temperatures: band_temperatures,
+            });
+            y = band.end;
+        }
+        borrowed
+    }
+
+    pub fn temperatures(&self) -> impl Iterator<Item = (SandboxCoordinate, f32)> + '_ {
+        self.temperatures
+            .iter()
+            .enumerate()
+            .map(move |(i, &temperature)| (self.coord(i), temperature))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SandboxCoordinate, &Atom)> {
+        self.array
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, cell)| cell.as_ref().map(|atom| (self.coord(i), atom)))
+    }
+}
+
+impl<A: Storage<Option<Atom>>, T: Storage<f32>> Cells<A, T> {
+    fn index(&self, coord: SandboxCoordinate) -> usize {
+        ((coord.y - self.origin.y) * self.size.0 + coord.x - self.origin.x) as usize
+    }
+
+    fn coord(&self, index: usize) -> SandboxCoordinate {
+        SandboxCoordinate {
+            x: self.origin.x + index as i32 % self.size.0,
+            y: self.origin.y + index as i32 / self.size.0,
         }
     }
 
-    pub fn fill_cell(&mut self, atom: Atom) -> Result<()> {
-        if let Some(atom_exists) = self.get_cell_contents(atom.coord) {
+    pub fn contains(&self, coord: SandboxCoordinate) -> bool {
+        coord.x >= self.origin.x
+            && coord.x < self.origin.x + self.size.0
+            && coord.y >= self.origin.y
+            && coord.y < self.origin.y + self.size.1
+    }
+
+    pub fn fill_cell(&mut self, coord: SandboxCoordinate, atom: Atom) -> Result<()> {
+        let index = self.index(coord);
+        let cell = &mut self.array.as_mut()[index];
+        if cell.is_some() {
             Err(CellsError::CouldNotFillCell {
-                x: atom_exists.coord.x,
-                y: atom_exists.coord.y,
+                x: coord.x,
+                y: coord.y,
             }
             .into())
         } else {
-            self.array[(atom.coord.y * self.size.0 + atom.coord.x) as usize] = Some(atom);
+            *cell = Some(atom);
             Ok(())
         }
     }
 
-    pub fn clear_cell(&mut self, coord: SandboxCoordinate) {
-        self.array[(coord.y * self.size.0 + coord.x) as usize] = None;
+    pub fn clear_cell(&mut self, coord: SandboxCoordinate) -> Option<Atom> {
+        let index = self.index(coord);
+        self.array.as_mut()[index].take()
     }
+
     pub fn get_cell_contents(&self, coord: SandboxCoordinate) -> Option<Atom> {
-        self.array[(coord.y * self.size.0 + coord.x) as usize]
+        self.array.as_ref()[self.index(coord)]
     }
 
-    // fn get_atom_neighbourhood(&self, atom: Atom) -> Vec<bool> {
-    //     let nh = vec![];
-    //     for dy in -1..2 {
-    //         for dx in -1..2 {
-    //             if dx == 0 && dy == 0 {
-    //                 continue;
-    //             }
-    //             let target = SandboxCoordinate {
-    //                 x: atom.coord.x + dx,
-    //                 y: atom.coord.y + dy,
-    //             };
-    //             if self.atom_out_of_bounds(target) || self.get(target) {
-    //                 neighbourhood.push(true);
-    //             } else {
-    //                 neighbourhood.push(false);
-    //             }
-    //             self.get_cell_contents(coord)
-    //         }
-    //     }
-    //     vec![false; 8]
-    // }
+    pub fn get_cell_contents_mut(&mut self, coord: SandboxCoordinate) -> Option<&mut Atom> {
+        let index = self.index(coord);
+        self.array.as_mut()[index].as_mut()
+    }
+
+    pub fn swap_cells(&mut self, a: SandboxCoordinate, b: SandboxCoordinate) {
+        let (a, b) = (self.index(a), self.index(b));
+        self.array.as_mut().swap(a, b);
+        self.temperatures.as_mut().swap(a, b);
+    }
+
+    pub fn temperature(&self, coord: SandboxCoordinate) -> f32 {
+        self.temperatures.as_ref()[self.index(coord)]
+    }
+
+    pub fn set_temperature(&mut self, coord: SandboxCoordinate, temperature: f32) {
+        let index = self.index(coord);
+        self.temperatures.as_mut()[index] = temperature;
+    }
 }
@@ -0,0 +1,142 @@
+// Heat. Every cell has a temperature, whether there's an atom in it or not, which travels with
+// the atom when it moves. Each tick heat flows between neighbouring cells, more quickly between
+// good conductors, and empty cells slowly give their heat up to the world outside the sandbox.
+// Heat only moves in and out of cells in awake chunks, and anywhere the temperature is still
+// changing quickly is kept awake for the next tick.
+use super::cells::Cells;
+use super::sweep::{self, Chunk, DirtyRect};
+use super::SandboxCoordinate;
+
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+// how empty cells conduct and hold heat
+const AIR_CONDUCTIVITY: f32 = 0.1;
+const AIR_SPECIFIC_HEAT: f32 = 1.0;
+// how much of the difference in temperature between two perfect conductors evens out in a tick
+const HEAT_RATE: f32 = 0.2;
+// how much of the way back to ambient an empty cell goes in a tick
+const AIR_COOLING: f32 = 0.01;
+// a cell whose temperature changes by less than this in a tick doesn't keep its chunk awake
+const WAKING_CHANGE: f32 = 0.1;
+
+fn thermal_properties(cells: &Cells, coord: SandboxCoordinate) -> (f32, f32) {
+    // conductivity and specific heat of whatever's in the cell
+    match cells.get_cell_contents(coord) {
+        Some(atom) => {
+            let properties = atom.element().properties();
+            (properties.conductivity, properties.specific_heat)
+        }
+        None => (AIR_CONDUCTIVITY, AIR_SPECIFIC_HEAT),
+    }
+}
+
+fn exchange(cells: &mut Cells, coord: SandboxCoordinate, other: SandboxCoordinate) -> f32 {
+    // evens out some of the difference in temperature between two touching cells, returning how
+    // much the one that changed most changed by
+    let (conductivity, specific_heat) = thermal_properties(cells, coord);
+    let (other_conductivity, other_specific_heat) = thermal_properties(cells, other);
+    // a pair conducts as well as their harmonic mean, so an insulator on either side keeps heat
+    // in
+    let sum = conductivity + other_conductivity;
+    if sum <= 0.0 {
+        return 0.0;
+    }
+    let rate = HEAT_RATE * 2.0 * conductivity * other_conductivity / sum;
+    let (t, other_t) = (cells.temperature(coord), cells.temperature(other));
+    let heat = rate * (t - other_t) * specific_heat.min(other_specific_heat);
+    cells.set_temperature(coord, t - heat / specific_heat);
+    cells.set_temperature(other, other_t + heat / other_specific_heat);
+    (heat / specific_heat.min(other_specific_heat)).abs()
+}
+
+pub fn conduct(
+    cells: &mut Cells,
+    sandbox_w: i32,
+    sandbox_h: i32,
+    chunks: &[Chunk],
+    awake: &[bool],
+) -> Vec<DirtyRect> {
+    // exchanges heat across every pair of touching cells with at least one of them in an awake
+    // chunk, once each: by looking right and down from each cell in an awake chunk, and left and
+    // up from its edges too where the chunk beyond is asleep. the heat is moved in place, so it's
+    // conserved exactly, and no exchange can do more than even out the pair so it never
+    // overshoots. returns, for each awake chunk, a rectangle around the cells whose temperature
+    // changed enough to keep them awake
+    let mut warmed = vec![];
+    for chunk in chunks.iter().filter(|chunk| awake[chunk.index]) {
+        let mut changed = None;
+        for coord in chunk.cells() {
+            let (x, y) = (coord.x, coord.y);
+            for (other, backwards) in [
+                (SandboxCoordinate { x: x + 1, y }, false),
+                (SandboxCoordinate { x, y: y + 1 }, false),
+                (SandboxCoordinate { x: x - 1, y }, true),
+                (SandboxCoordinate { x, y: y - 1 }, true),
+            ] {
+                if other.x < 0 || other.y < 0 || other.x >= sandbox_w || other.y >= sandbox_h {
+                    continue;
+                }
+                if backwards && (chunk.contains(other) || awake[sweep::chunk_at(sandbox_w, other)])
+                {
+                    continue;
+                }
+                if exchange(cells, coord, other) > WAKING_CHANGE {
+                    changed = Some(DirtyRect::including(changed, coord));
+                    changed = Some(DirtyRect::including(changed, other));
+                }
+            }
+        }
+        for coord in chunk.cells() {
+            if cells.get_cell_contents(coord).is_none() {
+                let t = cells.temperature(coord);
+                let cooling = (AMBIENT_TEMPERATURE - t) * AIR_COOLING;
+                cells.set_temperature(coord, t + cooling);
+                if cooling.abs() > WAKING_CHANGE {
+                    changed = Some(DirtyRect::including(changed, coord));
+                }
+            }
+        }
+        warmed.extend(changed);
+    }
+    warmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heat_evens_out_without_overshooting() {
+        let mut cells = Cells::new(2, 1);
+        let chunks = sweep::chunks(2, 1);
+        cells.set_temperature(SandboxCoordinate { x: 0, y: 0 }, 100.0);
+        let mut last = 100.0;
+        for _ in 0..500 {
+            conduct(&mut cells, 2, 1, &chunks, &[true]);
+            let (hot, cold) = (
+                cells.temperature(SandboxCoordinate { x: 0, y: 0 }),
+                cells.temperature(SandboxCoordinate { x: 1, y: 0 }),
+            );
+            assert!(hot <= last && hot >= cold);
+            last = hot;
+        }
+        // and with nothing but air, it all leaks back out to ambient in the end
+        assert!((last - AMBIENT_TEMPERATURE).abs() < 1.0);
+    }
+
+    #[test]
+    fn heat_crosses_into_chunks_that_are_asleep() {
+        // a hot cell on the edge of an awake chunk warms the sleeping chunk next to it, which is
+        // then woken, but the rest of the sleeping chunk is left alone
+        let mut cells = Cells::new(64, 32);
+        let chunks = sweep::chunks(64, 32);
+        cells.set_temperature(SandboxCoordinate { x: 31, y: 0 }, 1000.0);
+        cells.set_temperature(SandboxCoordinate { x: 63, y: 31 }, 1000.0);
+        let warmed = conduct(&mut cells, 64, 32, &chunks, &[true, false]);
+        assert!(cells.temperature(SandboxCoordinate { x: 32, y: 0 }) > AMBIENT_TEMPERATURE);
+        assert_eq!(
+            cells.temperature(SandboxCoordinate { x: 63, y: 31 }),
+            1000.0
+        );
+        assert!(warmed.iter().any(|rect| rect.max.x >= 32));
+    }
+}
@@ -1,17 +1,40 @@
+use std::ops::Range;
+use std::sync::Arc;
+
 use anyhow::Result;
 
 use ggez::mint::Vector2;
+use glam::Vec2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use thiserror::Error;
 
 pub mod parameters;
 use parameters::*;
+pub mod air;
+use air::*;
 pub mod atom;
 use atom::*;
+pub mod brush;
+use brush::*;
 pub mod cells;
 use cells::*;
+pub mod heat;
+pub mod neighbourhood;
+pub mod registry;
+use registry::*;
+pub mod snapshot;
+pub mod sweep;
+use sweep::{Chunk, DirtyRect, Sweep};
 
 pub type SandboxCoordinate = Vector2<i32>;
 
+// how much the pressure tool adds to the air under each cell of the brush, per frame
+const PRESSURE_TOOL_STRENGTH: f32 = 0.2;
+// and how much the wind tool speeds it up, in the direction the mouse is dragged
+const WIND_TOOL_STRENGTH: f32 = 0.05;
+
 /* Module error */
 #[derive(Error, Debug)]
 pub enum StateError {
@@ -20,31 +43,103 @@ pub enum StateError {
 }
 
 /* Subtypes and structs */
-pub type Atoms = Vec<Atom>;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Element(Element),
+    Eraser,
+    // raises the air pressure under the brush
+    Pressure,
+    // blows the air under the brush along the direction the mouse is dragged
+    Wind,
+}
 
 /* State */
 #[derive(Clone)]
 pub struct State {
     pub parameters: Parameters,
+    pub brush: Brush,
     cells: Cells,
-    atoms: Atoms,
+    air: Air,
+    // counts up every tick; an atom whose clock already matches has been updated this tick
+    clock: u32,
+    // the chunks the sandbox is swept in, which of them to sweep on the next tick, and which
+    // were swept on the last one; the rest are asleep
+    chunks: Vec<Chunk>,
+    awake: Vec<bool>,
+    swept: Vec<bool>,
+    // what gravity was on the last tick; if it's changed since, everything wakes up
+    swept_gravity: Gravity,
+    // the threads chunks are swept on, when there's more than one; started on the first tick
+    // that needs them and kept for as long as the thread count stays the same
+    pool: Option<Arc<ThreadPool>>,
+    // every random choice in the simulation comes from here, so a run can be replayed from its
+    // seed
+    seed: u64,
+    rng: StdRng,
     active_element: Element,
+    active_tool: Tool,
 }
 
 impl State {
-    pub fn new(sandbox_size: i32) -> Self {
+    pub fn new(sandbox_w: i32, sandbox_h: i32) -> Self {
+        let seed = rand::random();
+        // start out painting with whichever element is defined first
+        let first_element = ElementRegistry::global()
+            .elements()
+            .next()
+            .expect("Registry has no elements");
+        let parameters = Parameters::new(sandbox_w, sandbox_h);
+        let chunks = sweep::chunks(sandbox_w, sandbox_h);
         State {
-            parameters: Parameters::new(sandbox_size),
-            atoms: vec![],
-            cells: Cells::new(sandbox_size),
-            active_element: Element::Sand,
+            brush: Brush::default(),
+            cells: Cells::new(sandbox_w, sandbox_h),
+            air: Air::new(sandbox_w, sandbox_h),
+            clock: 0,
+            awake: vec![true; chunks.len()],
+            swept: vec![false; chunks.len()],
+            chunks,
+            swept_gravity: parameters.gravity,
+            pool: None,
+            parameters,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            active_element: first_element,
+            active_tool: Tool::Element(first_element),
         }
     }
 
     pub fn init(&mut self) {}
 
-    pub fn set_active_element(&mut self, el: Element) {
-        self.active_element = el;
+    pub fn clear(&mut self) {
+        // rebuild from scratch so that nothing from the previous run survives; only the user's
+        // tool and brush selection and gravity carry over, along with the seed so the cleared
+        // sandbox plays out the same as the one before it, and the threads it's swept on
+        let (active_element, active_tool, brush) =
+            (self.active_element, self.active_tool, self.brush);
+        let (seed, parameters, pool) = (self.seed, self.parameters.clone(), self.pool.take());
+        *self = State::new(self.parameters.sandbox_w, self.parameters.sandbox_h);
+        self.set_seed(seed);
+        self.parameters = parameters;
+        self.pool = pool;
+        self.active_element = active_element;
+        self.active_tool = active_tool;
+        self.brush = brush;
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_active_tool(&mut self, tool: Tool) {
+        if let Tool::Element(el) = tool {
+            self.active_element = el;
+        }
+        self.active_tool = tool;
     }
 
     fn atom_out_of_bounds(&self, coord: SandboxCoordinate) -> bool {
@@ -56,10 +151,85 @@ impl State {
 
     fn atom_exists_here(&self, coord: SandboxCoordinate) -> bool {
         log::debug!("{coord:?}");
-        if let Some(_) = self.cells.get_cell_contents(coord) {
-            return true;
+        self.cells.get_cell_contents(coord).is_some()
+    }
+
+    pub fn use_active_tool(&mut self, coord: SandboxCoordinate, drag: SandboxCoordinate) {
+        // paints the active tool with the brush centred on coord; drag is how far the mouse has
+        // moved since it was last painted, which only the wind cares about
+        match self.active_tool {
+            Tool::Element(_) => {
+                let area = self.brush.cells(coord, &mut self.rng);
+                self.make_atoms_in_area(area);
+            }
+            Tool::Eraser => self.erase(coord),
+            Tool::Pressure => {
+                for coord in self.brush_area_in_bounds(coord) {
+                    self.air.add_pressure(coord, PRESSURE_TOOL_STRENGTH);
+                    self.wake(DirtyRect {
+                        min: coord,
+                        max: coord,
+                    });
+                }
+            }
+            Tool::Wind => {
+                let direction = Vec2::new(drag.x as f32, drag.y as f32).normalize_or_zero();
+                for coord in self.brush_area_in_bounds(coord) {
+                    self.air.add_velocity(coord, direction * WIND_TOOL_STRENGTH);
+                    self.wake(DirtyRect {
+                        min: coord,
+                        max: coord,
+                    });
+                }
+            }
+        }
+    }
+
+    fn brush_area_in_bounds(&mut self, coord: SandboxCoordinate) -> Vec<SandboxCoordinate> {
+        let area = self.brush.cells(coord, &mut self.rng);
+        area.into_iter()
+            .filter(|&coord| !self.atom_out_of_bounds(coord))
+            .collect()
+    }
+
+    pub fn air(&self) -> &Air {
+        &self.air
+    }
+
+    pub fn active_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        // the chunks that were swept on the last tick
+        self.chunks
+            .iter()
+            .filter(move |chunk| self.swept[chunk.index])
+    }
+
+    pub fn wake_all(&mut self) {
+        self.awake.fill(true);
+        self.swept.fill(false);
+    }
+
+    fn wake(&mut self, rect: DirtyRect) {
+        // wakes every chunk in or next to rect for the next tick
+        let across = sweep::chunks_across(self.parameters.sandbox_w);
+        let (w, h) = (self.parameters.sandbox_w, self.parameters.sandbox_h);
+        let (cx0, cy0) = (
+            (rect.min.x - 1).max(0) / sweep::CHUNK_SIZE,
+            (rect.min.y - 1).max(0) / sweep::CHUNK_SIZE,
+        );
+        let (cx1, cy1) = (
+            (rect.max.x + 1).min(w - 1) / sweep::CHUNK_SIZE,
+            (rect.max.y + 1).min(h - 1) / sweep::CHUNK_SIZE,
+        );
+        for cy in cy0..=cy1 {
+            for cx in cx0..=cx1 {
+                self.awake[(cy * across + cx) as usize] = true;
+            }
         }
-        false
+    }
+
+    pub fn erase(&mut self, coord: SandboxCoordinate) {
+        let area = self.brush.cells(coord, &mut self.rng);
+        self.remove_atoms_in_area(area);
     }
 
     pub fn make_atom(&mut self, coord: SandboxCoordinate) -> Result<(), StateError> {
@@ -70,62 +240,225 @@ impl State {
                 "Atom already exists here",
             )))
         } else {
-            self.atoms.push(Atom::new(coord, self.active_element));
+            let atom = sweep::new_atom(self.active_element, self.clock, &mut self.rng);
             self.cells
-                .fill_cell(Atom::new(coord, self.active_element))
+                .fill_cell(coord, atom)
                 .expect("Couldn't fill cell");
+            self.cells
+                .set_temperature(coord, self.active_element.properties().temperature);
+            self.wake(DirtyRect {
+                min: coord,
+                max: coord,
+            });
             Ok(())
         }
     }
 
-    pub fn get_atoms(&self) -> &Atoms {
-        &self.atoms
+    pub fn remove_atom(&mut self, coord: SandboxCoordinate) -> Result<(), StateError> {
+        if self.atom_out_of_bounds(coord) {
+            Err(StateError::AtomError(String::from("Atom out of bounds")))
+        } else if !self.atom_exists_here(coord) {
+            Err(StateError::AtomError(String::from("No atom exists here")))
+        } else {
+            self.cells.clear_cell(coord);
+            self.wake(DirtyRect {
+                min: coord,
+                max: coord,
+            });
+            Ok(())
+        }
     }
 
-    fn get_atom_neighbourhood(&self, atom: &Atom) -> Vec<bool> {
-        let mut neighbourhood: Vec<bool> = vec![];
-        for dy in -1..2 {
-            for dx in -1..2 {
-                if dx == 0 && dy == 0 {
-                    continue;
-                }
-                let target = SandboxCoordinate {
-                    x: atom.coord.x + dx,
-                    y: atom.coord.y + dy,
-                };
-                if self.atom_out_of_bounds(target) || self.atom_exists_here(target) {
-                    neighbourhood.push(true);
-                } else {
-                    neighbourhood.push(false);
-                }
-            }
-        }
-        assert_eq!(neighbourhood.len(), 8);
-        neighbourhood
+    pub fn make_atoms_in_area(
+        &mut self,
+        area: impl IntoIterator<Item = SandboxCoordinate>,
+    ) -> usize {
+        // makes an atom in every cell of area, silently skipping occupied and out of bounds
+        // cells; returns how many atoms were made
+        area.into_iter()
+            .filter(|&coord| self.make_atom(coord).is_ok())
+            .count()
+    }
+
+    pub fn remove_atoms_in_area(
+        &mut self,
+        area: impl IntoIterator<Item = SandboxCoordinate>,
+    ) -> usize {
+        // removes every atom in area, silently skipping empty and out of bounds cells; returns
+        // how many atoms were removed
+        area.into_iter()
+            .filter(|&coord| self.remove_atom(coord).is_ok())
+            .count()
+    }
+
+    pub fn temperatures(&self) -> impl Iterator<Item = (SandboxCoordinate, f32)> + '_ {
+        self.cells.temperatures()
+    }
+
+    pub fn atoms(&self) -> impl Iterator<Item = (SandboxCoordinate, &Atom)> {
+        self.cells.iter()
     }
 
     pub fn update_atoms(&mut self) {
-        // let atoms_copy = self.atoms.clone();
-        let self_copy = self.clone();
-        for atom in &mut self.atoms {
-            let nh = self_copy.get_atom_neighbourhood(atom);
-            // optim: if neighbourhood is obviously full dont bother doing anything
-            if nh[3..7] != [true, true, true, true, true] {
-                atom.set_next(nh);
-            }
-        }
-        for atom in &mut self.atoms {
-            // destination cell might have been filled by another atom
-            // if so, don't move, and clear next coord
-            if let Some(_) = self.cells.get_cell_contents(atom.next_coord) {
-                atom.reset_next();
+        // changing gravity unsettles everything
+        if self.parameters.gravity != self.swept_gravity {
+            self.swept_gravity = self.parameters.gravity;
+            self.awake.fill(true);
+        }
+        // anything woken from here on is woken for the next tick
+        let awake = std::mem::replace(&mut self.awake, vec![false; self.chunks.len()]);
+
+        self.clock = self.clock.wrapping_add(1);
+        self.update_temperature(&awake);
+        // the wind can blow settled atoms about
+        let stirred = self.air.update(
+            &self.cells,
+            self.parameters.sandbox_w,
+            self.parameters.sandbox_h,
+            &self.chunks,
+            &awake,
+        );
+        for rect in stirred {
+            self.wake(rect);
+        }
+        let tick_seed = self.rng.gen();
+        // alternate which pass goes first, so no chunk is always swept before its neighbours
+        let passes = if self.clock % 2 == 1 {
+            [0, 1, 2, 3]
+        } else {
+            [3, 2, 1, 0]
+        };
+        for pass in passes {
+            let chunks: Vec<Chunk> = self
+                .chunks
+                .iter()
+                .filter(|chunk| chunk.pass == pass && awake[chunk.index])
+                .copied()
+                .collect();
+            let (blasts, dirty) = if self.parameters.threads > 1 {
+                self.sweep_in_parallel(&chunks, tick_seed)
             } else {
-                self.cells.clear_cell(atom.coord);
-                atom.update();
-                self.cells
-                    .fill_cell(atom.clone())
-                    .expect("Couldn't fill cell");
+                self.sweep_in_turn(&chunks, tick_seed)
+            };
+            for (coord, blast) in blasts {
+                self.air.add_pressure(coord, blast);
+                self.wake(DirtyRect {
+                    min: coord,
+                    max: coord,
+                });
+            }
+            for rect in dirty {
+                self.wake(rect);
+            }
+        }
+        self.swept = awake;
+    }
+
+    fn sweep_in_turn(
+        &mut self,
+        chunks: &[Chunk],
+        tick_seed: u64,
+    ) -> (Vec<(SandboxCoordinate, f32)>, Vec<DirtyRect>) {
+        let mut sweep = Sweep::new(
+            self.cells.rows_mut(),
+            &self.air,
+            self.parameters.gravity,
+            self.clock,
+            tick_seed,
+        );
+        for chunk in chunks {
+            sweep.chunk(chunk);
+        }
+        (sweep.blasts, sweep.dirty)
+    }
+
+    fn sweep_in_parallel(
+        &mut self,
+        chunks: &[Chunk],
+        tick_seed: u64,
+    ) -> (Vec<(SandboxCoordinate, f32)>, Vec<DirtyRect>) {
+        // each row of chunks is swept in turn, in the band of rows it can reach, and the bands
+        // are shared out between the pool's threads
+        let threads = self.parameters.threads;
+        let pool = match &self.pool {
+            Some(pool) if pool.current_num_threads() == threads => pool.clone(),
+            _ => {
+                let pool = Arc::new(
+                    ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .expect("Couldn't start sweep threads"),
+                );
+                self.pool = Some(pool.clone());
+                pool
             }
+        };
+        let rows: Vec<&[Chunk]> = chunks.chunk_by(|a, b| a.origin.y == b.origin.y).collect();
+        let bands: Vec<Range<i32>> = rows
+            .iter()
+            .map(|row| row[0].band(self.parameters.sandbox_h))
+            .collect();
+        let (air, gravity, clock) = (&self.air, self.parameters.gravity, self.clock);
+        let bands = self.cells.bands_mut(&bands);
+        let swept: Vec<_> = pool.install(|| {
+            bands
+                .into_par_iter()
+                .zip(rows)
+                .map(|(band, row)| {
+                    let mut sweep = Sweep::new(band, air, gravity, clock, tick_seed);
+                    for chunk in row {
+                        sweep.chunk(chunk);
+                    }
+                    (sweep.blasts, sweep.dirty)
+                })
+                .collect()
+        });
+        let (mut blasts, mut dirty) = (vec![], vec![]);
+        for (mut band_blasts, mut band_dirty) in swept {
+            blasts.append(&mut band_blasts);
+            dirty.append(&mut band_dirty);
+        }
+        (blasts, dirty)
+    }
+
+    fn update_temperature(&mut self, awake: &[bool]) {
+        let warmed = heat::conduct(
+            &mut self.cells,
+            self.parameters.sandbox_w,
+            self.parameters.sandbox_h,
+            &self.chunks,
+            awake,
+        );
+        for rect in warmed {
+            self.wake(rect);
+        }
+        // then anything awake that's got too hot or too cold changes into something else,
+        // keeping its temperature
+        let transitions: Vec<_> = self
+            .chunks
+            .iter()
+            .filter(|chunk| awake[chunk.index])
+            .flat_map(|chunk| chunk.cells())
+            .filter_map(|coord| {
+                let atom = self.cells.get_cell_contents(coord)?;
+                let temperature = self.cells.temperature(coord);
+                atom.element()
+                    .transition(temperature)
+                    .map(|element| (coord, element))
+            })
+            .collect();
+        for (coord, element) in transitions {
+            sweep::replace_atom(
+                &mut self.cells,
+                coord,
+                Some(element),
+                self.clock,
+                &mut self.rng,
+            );
+            self.wake(DirtyRect {
+                min: coord,
+                max: coord,
+            });
         }
     }
 }
@@ -134,44 +467,65 @@ impl State {
 mod tests {
     use super::*;
 
+    const STILL: SandboxCoordinate = SandboxCoordinate { x: 0, y: 0 };
+
+    fn el(name: &str) -> Element {
+        Element::named(name).unwrap()
+    }
+
+    fn occupied(state: &State, x: i32, y: i32) -> bool {
+        state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x, y })
+            .is_some()
+    }
+
     #[test]
     fn make_and_update_three_atoms() {
         // init with two atoms
-        let mut state = State::new(10);
+        let mut state = State::new(10, 10);
         state.make_atom(SandboxCoordinate { x: 3, y: 3 }).ok();
         state.make_atom(SandboxCoordinate { x: 4, y: 4 }).ok();
         // these two should fall straight down
         state.update_atoms();
-        if let Some(atom) = state.get_atoms().get(0) {
-            assert_eq!(atom.coord, SandboxCoordinate { x: 3, y: 4 });
-        }
-        if let Some(atom) = state.get_atoms().get(1) {
-            assert_eq!(atom.coord, SandboxCoordinate { x: 4, y: 5 });
-        }
+        assert!(occupied(&state, 3, 4));
+        assert!(occupied(&state, 4, 5));
         // add a third atom under the top one
         state.make_atom(SandboxCoordinate { x: 3, y: 5 }).ok();
-        // top one should now fall down to the left
+        // lower atoms are updated first, so the top one falls straight down after them
         state.update_atoms();
-        if let Some(atom) = state.get_atoms().get(0) {
-            assert_eq!(atom.coord, SandboxCoordinate { x: 2, y: 5 });
-        }
+        assert!(occupied(&state, 3, 5));
+        assert!(occupied(&state, 3, 6));
+        assert!(occupied(&state, 4, 6));
+        assert_eq!(state.atoms().count(), 3);
     }
 
     #[test]
     fn atom_collides_with_ground() {
-        let mut state = State::new(5);
+        let mut state = State::new(5, 5);
         state.make_atom(SandboxCoordinate { x: 2, y: 2 }).ok();
         state.update_atoms(); // should be at [2,3]
         state.update_atoms(); // should be at [2,4]
         state.update_atoms(); // should be at [2,4]
-        if let Some(atom) = state.get_atoms().get(0) {
-            assert_eq!(atom.coord, SandboxCoordinate { x: 2, y: 4 });
-        }
+        assert!(occupied(&state, 2, 4));
+        assert_eq!(state.atoms().count(), 1);
+    }
+
+    #[test]
+    fn atom_moves_once_per_tick() {
+        // atom lands in a row that hasn't been swept yet, but shouldn't be moved again
+        let mut state = State::new(5, 10);
+        state.make_atom(SandboxCoordinate { x: 2, y: 0 }).ok();
+        state.update_atoms();
+        assert!(occupied(&state, 2, 1));
+        state.update_atoms();
+        assert!(occupied(&state, 2, 2));
+        assert_eq!(state.atoms().count(), 1);
     }
 
     #[test]
     fn cells_updates_in_correct_spot() {
-        let mut state = State::new(5);
+        let mut state = State::new(5, 5);
         state.make_atom(SandboxCoordinate { x: 2, y: 2 }).ok();
         assert!(state
             .cells
@@ -188,4 +542,876 @@ mod tests {
             .get_cell_contents(SandboxCoordinate { x: 2, y: 3 })
             .is_some());
     }
+
+    #[test]
+    fn remove_atom_clears_atoms_and_cells() {
+        let mut state = State::new(5, 5);
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).ok();
+        state.make_atom(SandboxCoordinate { x: 3, y: 1 }).ok();
+        assert!(state.remove_atom(SandboxCoordinate { x: 1, y: 1 }).is_ok());
+        assert_eq!(
+            state.atoms().map(|(coord, _)| coord).collect::<Vec<_>>(),
+            vec![SandboxCoordinate { x: 3, y: 1 }]
+        );
+        assert!(state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x: 1, y: 1 })
+            .is_none());
+        // removing from an empty or out of bounds cell is an error
+        assert!(state.remove_atom(SandboxCoordinate { x: 1, y: 1 }).is_err());
+        assert!(state
+            .remove_atom(SandboxCoordinate { x: -1, y: 1 })
+            .is_err());
+    }
+
+    #[test]
+    fn remove_atoms_in_area_skips_empty_cells() {
+        let mut state = State::new(5, 5);
+        state.make_atom(SandboxCoordinate { x: 0, y: 0 }).ok();
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).ok();
+        state.make_atom(SandboxCoordinate { x: 4, y: 4 }).ok();
+        let brush = Brush {
+            radius: 1,
+            shape: BrushShape::Square,
+        };
+        let area = brush.cells(SandboxCoordinate { x: 0, y: 0 }, &mut state.rng);
+        assert_eq!(state.remove_atoms_in_area(area), 2);
+        assert_eq!(state.atoms().count(), 1);
+        assert!(state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x: 4, y: 4 })
+            .is_some());
+    }
+
+    #[test]
+    fn eraser_tool_removes_atoms() {
+        let mut state = State::new(5, 5);
+        state.brush.radius = 0;
+        state.use_active_tool(SandboxCoordinate { x: 2, y: 2 }, STILL);
+        assert_eq!(state.atoms().count(), 1);
+        state.set_active_tool(Tool::Eraser);
+        state.use_active_tool(SandboxCoordinate { x: 2, y: 2 }, STILL);
+        assert!(state.atoms().next().is_none());
+    }
+
+    #[test]
+    fn brush_paints_area_and_skips_occupied_cells() {
+        let mut state = State::new(5, 5);
+        state.make_atom(SandboxCoordinate { x: 2, y: 2 }).ok();
+        state.brush = Brush {
+            radius: 1,
+            shape: BrushShape::Square,
+        };
+        state.use_active_tool(SandboxCoordinate { x: 2, y: 2 }, STILL);
+        assert_eq!(state.atoms().count(), 9);
+        // brush hanging off the edge of the sandbox only paints the cells inside it
+        state.clear();
+        state.use_active_tool(SandboxCoordinate { x: 0, y: 0 }, STILL);
+        assert_eq!(state.atoms().count(), 4);
+    }
+
+    #[test]
+    fn clear_empties_atoms_and_cells() {
+        let mut state = State::new(5, 5);
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).ok();
+        state.make_atom(SandboxCoordinate { x: 3, y: 3 }).ok();
+        state.update_atoms();
+        state.set_active_tool(Tool::Element(el("water")));
+        state.clear();
+        assert!(state.atoms().next().is_none());
+        for y in 0..5 {
+            for x in 0..5 {
+                assert!(state
+                    .cells
+                    .get_cell_contents(SandboxCoordinate { x, y })
+                    .is_none());
+            }
+        }
+        // sandbox is still usable and keeps the selected element
+        assert!(state.make_atom(SandboxCoordinate { x: 3, y: 3 }).is_ok());
+        assert_eq!(state.active_tool, Tool::Element(el("water")));
+    }
+
+    #[test]
+    fn rectangular_sandbox_bounds() {
+        let mut state = State::new(8, 3);
+        assert!(state.make_atom(SandboxCoordinate { x: 7, y: 0 }).is_ok());
+        assert!(state.make_atom(SandboxCoordinate { x: 0, y: 2 }).is_ok());
+        assert!(state.make_atom(SandboxCoordinate { x: 8, y: 0 }).is_err());
+        assert!(state.make_atom(SandboxCoordinate { x: 0, y: 3 }).is_err());
+        // atom in the far column should land on the floor, not wrap into another row
+        state.update_atoms();
+        state.update_atoms();
+        assert!(state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x: 7, y: 2 })
+            .is_some());
+        assert!(state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x: 0, y: 2 })
+            .is_some());
+    }
+
+    fn occupied_cells(state: &State) -> Vec<SandboxCoordinate> {
+        state.atoms().map(|(coord, _)| coord).collect()
+    }
+
+    #[test]
+    fn column_falls_together() {
+        let mut state = State::new(3, 6);
+        for y in 0..3 {
+            state.make_atom(SandboxCoordinate { x: 1, y }).ok();
+        }
+        state.update_atoms();
+        assert_eq!(
+            occupied_cells(&state),
+            (1..4)
+                .map(|y| SandboxCoordinate { x: 1, y })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn update_is_independent_of_creation_order() {
+        // a column against the left wall only ever has one way to go at each step, so the
+        // outcome is fixed, and should be the same whichever end the column was built from
+        let column: Vec<_> = (0..4).map(|y| SandboxCoordinate { x: 0, y }).collect();
+        let mut top_first = State::new(3, 6);
+        let mut bottom_first = State::new(3, 6);
+        for &coord in column.iter() {
+            top_first.make_atom(coord).ok();
+        }
+        for &coord in column.iter().rev() {
+            bottom_first.make_atom(coord).ok();
+        }
+        for _ in 0..6 {
+            top_first.update_atoms();
+            bottom_first.update_atoms();
+            assert_eq!(occupied_cells(&top_first), occupied_cells(&bottom_first));
+        }
+        assert_eq!(
+            occupied_cells(&top_first),
+            vec![
+                SandboxCoordinate { x: 0, y: 4 },
+                SandboxCoordinate { x: 0, y: 5 },
+                SandboxCoordinate { x: 1, y: 5 },
+                SandboxCoordinate { x: 2, y: 5 },
+            ]
+        );
+    }
+
+    fn pour_water(state: &mut State) {
+        // a splash of water on a wide floor has plenty of coin flips to make
+        state.set_active_tool(Tool::Element(el("water")));
+        for x in 6..10 {
+            state.make_atom(SandboxCoordinate { x, y: 0 }).ok();
+            state.make_atom(SandboxCoordinate { x, y: 1 }).ok();
+        }
+        for _ in 0..8 {
+            state.update_atoms();
+        }
+    }
+
+    fn seeded(seed: u64) -> State {
+        let mut state = State::new(16, 4);
+        state.set_seed(seed);
+        state
+    }
+
+    fn busy_sandbox() -> State {
+        // several chunks' worth of everything, with chunk edges running through all of it
+        let mut state = State::new(100, 70);
+        state.set_seed(11);
+        let elements: Vec<Element> = ElementRegistry::global().elements().collect();
+        for y in 10..70 {
+            for x in 0..100 {
+                if (x + y) % 3 != 0 {
+                    state.set_active_tool(Tool::Element(
+                        elements[((x / 7 + y / 5) % elements.len() as i32) as usize],
+                    ));
+                    state.make_atom(SandboxCoordinate { x, y }).unwrap();
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn sweeping_in_parallel_matches_sweeping_in_turn() {
+        let mut in_turn = busy_sandbox();
+        let mut in_parallel = in_turn.clone();
+        in_parallel.parameters.threads = 4;
+        let saved = |state: &State| {
+            let mut bytes = vec![];
+            snapshot::save(state, &mut bytes).unwrap();
+            bytes
+        };
+        for _ in 0..60 {
+            in_turn.update_atoms();
+            in_parallel.update_atoms();
+        }
+        assert!(saved(&in_turn) == saved(&in_parallel));
+    }
+
+    #[test]
+    fn sleeping_chunks_dont_change_the_result() {
+        // chunks only sleep when nothing in them would have happened anyway, so keeping every
+        // chunk awake plays out the same
+        let mut sleeping = State::new(160, 70);
+        sleeping.set_seed(11);
+        let elements: Vec<Element> = ElementRegistry::global().elements().collect();
+        for y in 10..70 {
+            for x in 0..160 {
+                // a bit of everything on the left, spilling out over a settled floor of sand
+                let element = if x < 100 && (x + y) % 3 != 0 {
+                    elements[((x / 7 + y / 5) % elements.len() as i32) as usize]
+                } else if x >= 100 && y >= 62 {
+                    el("sand")
+                } else {
+                    continue;
+                };
+                sleeping.set_active_tool(Tool::Element(element));
+                sleeping.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        let mut awake = sleeping.clone();
+        let cells = |state: &State| -> Vec<_> {
+            state
+                .atoms()
+                .map(|(coord, atom)| (coord, atom.element()))
+                .collect()
+        };
+        let mut slept = false;
+        for tick in 0..60 {
+            sleeping.update_atoms();
+            awake.wake_all();
+            awake.update_atoms();
+            assert!(
+                cells(&sleeping) == cells(&awake),
+                "different on tick {}",
+                tick
+            );
+            slept |= sleeping.active_chunks().count() < sleeping.chunks.len();
+        }
+        assert!(slept);
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        let mut state = seeded(7);
+        pour_water(&mut state);
+        let mut again = seeded(7);
+        pour_water(&mut again);
+        assert_eq!(occupied_cells(&state), occupied_cells(&again));
+        // and it's this result in particular, so a change in how randomness is used shows up
+        assert_eq!(
+            occupied_cells(&state),
+            [2, 4, 5, 6, 7, 8, 9, 10]
+                .iter()
+                .map(|&x| SandboxCoordinate { x, y: 3 })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn clear_keeps_seed() {
+        let mut state = seeded(3);
+        pour_water(&mut state);
+        let before = occupied_cells(&state);
+        state.clear();
+        assert_eq!(state.get_seed(), 3);
+        pour_water(&mut state);
+        assert_eq!(occupied_cells(&state), before);
+    }
+
+    fn atoms_on_floor(element: Element, ticks: usize) -> usize {
+        // drops a column of liquid and counts how much of it has spread out along the floor
+        let mut state = State::new(40, 8);
+        state.set_seed(11);
+        state.set_active_tool(Tool::Element(element));
+        for y in 0..8 {
+            state.make_atom(SandboxCoordinate { x: 20, y }).ok();
+        }
+        for _ in 0..ticks {
+            state.update_atoms();
+        }
+        state.atoms().filter(|(coord, _)| coord.y == 7).count()
+    }
+
+    #[test]
+    fn viscous_liquid_spreads_slower() {
+        let water = atoms_on_floor(el("water"), 30);
+        let honey = atoms_on_floor(el("honey"), 30);
+        assert_eq!(water, 8);
+        assert!(honey < water, "honey: {}, water: {}", honey, water);
+    }
+
+    fn element_at(state: &State, x: i32, y: i32) -> Option<Element> {
+        state
+            .cells
+            .get_cell_contents(SandboxCoordinate { x, y })
+            .map(|atom| atom.element())
+    }
+
+    #[test]
+    fn denser_atom_sinks_through_lighter() {
+        let mut state = State::new(1, 3);
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 2 }).ok();
+        state.set_active_tool(Tool::Element(el("sand")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 1 }).ok();
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 2), Some(el("sand")));
+        assert_eq!(element_at(&state, 0, 1), Some(el("water")));
+        // and stays put once it's at the bottom
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 2), Some(el("sand")));
+        assert_eq!(element_at(&state, 0, 1), Some(el("water")));
+    }
+
+    #[test]
+    fn liquids_layer_by_density() {
+        // oil poured under water ends up floating on top of it
+        let mut state = State::new(1, 4);
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 0 }).ok();
+        state.make_atom(SandboxCoordinate { x: 0, y: 1 }).ok();
+        state.set_active_tool(Tool::Element(el("oil")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 3 }).ok();
+        for _ in 0..6 {
+            state.update_atoms();
+        }
+        assert_eq!(element_at(&state, 0, 1), Some(el("oil")));
+        assert_eq!(element_at(&state, 0, 2), Some(el("water")));
+        assert_eq!(element_at(&state, 0, 3), Some(el("water")));
+    }
+
+    #[test]
+    fn solids_stay_put_and_hold_other_atoms() {
+        // a wall cup floating in the air keeps its shape and holds water
+        let mut state = State::new(5, 5);
+        let cup = [(1, 2), (1, 3), (2, 3), (3, 3), (3, 2)];
+        state.set_active_tool(Tool::Element(el("wall")));
+        for (x, y) in cup {
+            state.make_atom(SandboxCoordinate { x, y }).unwrap();
+        }
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 2, y: 0 }).unwrap();
+        for _ in 0..10 {
+            state.update_atoms();
+        }
+        for (x, y) in cup {
+            assert_eq!(element_at(&state, x, y), Some(el("wall")));
+        }
+        assert_eq!(element_at(&state, 2, 2), Some(el("water")));
+        assert_eq!(state.atoms().count(), cup.len() + 1);
+
+        // and a shelf of stone holds up sand
+        let mut state = State::new(3, 3);
+        state.set_active_tool(Tool::Element(el("stone")));
+        for x in 0..3 {
+            state.make_atom(SandboxCoordinate { x, y: 1 }).unwrap();
+        }
+        state.set_active_tool(Tool::Element(el("sand")));
+        state.make_atom(SandboxCoordinate { x: 1, y: 0 }).unwrap();
+        for _ in 0..5 {
+            state.update_atoms();
+        }
+        assert_eq!(element_at(&state, 1, 0), Some(el("sand")));
+        assert!((0..3).all(|x| element_at(&state, x, 1) == Some(el("stone"))));
+    }
+
+    #[test]
+    fn falling_atoms_speed_up() {
+        let mut state = State::new(3, 80);
+        state.make_atom(SandboxCoordinate { x: 1, y: 0 }).unwrap();
+        let mut heights = vec![];
+        for _ in 0..25 {
+            state.update_atoms();
+            heights.push(occupied_cells(&state)[0].y);
+        }
+        let falls: Vec<i32> = heights.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // one cell a tick to start with, then faster and faster
+        assert!(falls[..4].iter().all(|&fall| fall == 1));
+        assert!(falls.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(falls[falls.len() - 1] >= 4);
+    }
+
+    #[test]
+    fn falling_atoms_stop_at_the_first_obstacle_and_splash() {
+        // sand dropped from high enough lands on a wall rather than jumping through it, then
+        // skids off to one side
+        let mut state = State::new(41, 60);
+        state.set_active_tool(Tool::Element(el("wall")));
+        state.make_atom(SandboxCoordinate { x: 20, y: 50 }).unwrap();
+        state.set_active_tool(Tool::Element(el("sand")));
+        state.make_atom(SandboxCoordinate { x: 20, y: 0 }).unwrap();
+        let mut landed = None;
+        for _ in 0..60 {
+            state.update_atoms();
+            let sand = state
+                .atoms()
+                .find(|(_, atom)| atom.element() == el("sand"))
+                .unwrap();
+            if landed.is_none() && sand.0.y == 49 {
+                assert_eq!(sand.0.x, 20);
+                landed = Some(sand.1.velocity);
+            }
+        }
+        assert!(landed.unwrap().x.abs() >= 1.0);
+        let (coord, _) = state
+            .atoms()
+            .find(|(_, atom)| atom.element() == el("sand"))
+            .unwrap();
+        assert_eq!(coord.y, 59);
+        assert!((coord.x - 20).abs() > 1);
+    }
+
+    #[test]
+    fn atoms_fall_whichever_way_gravity_pulls() {
+        // with gravity pulling to the right, sand piles up against the right hand wall and
+        // smoke collects against the left
+        let mut state = State::new(10, 6);
+        state.set_seed(4);
+        state.parameters.gravity.direction = parameters::Direction::Right;
+        for y in 1..5 {
+            state.make_atom(SandboxCoordinate { x: 4, y }).unwrap();
+        }
+        state.set_active_tool(Tool::Element(el("smoke")));
+        state.make_atom(SandboxCoordinate { x: 6, y: 3 }).unwrap();
+        for _ in 0..30 {
+            state.update_atoms();
+        }
+        let sand: Vec<_> = state
+            .atoms()
+            .filter(|(_, atom)| atom.element() == el("sand"))
+            .map(|(coord, _)| coord.x)
+            .collect();
+        assert_eq!(sand.len(), 4);
+        assert!(sand.iter().all(|&x| x >= 8));
+        // (the smoke wanders about a bit once it gets there)
+        let (smoke, _) = state
+            .atoms()
+            .find(|(_, atom)| atom.element() == el("smoke"))
+            .unwrap();
+        assert!(smoke.x <= 1);
+    }
+
+    #[test]
+    fn nothing_falls_in_zero_g() {
+        let mut state = State::new(6, 6);
+        state.parameters.gravity.zero_g = true;
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).unwrap();
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 4, y: 2 }).unwrap();
+        for _ in 0..10 {
+            state.update_atoms();
+        }
+        assert_eq!(
+            occupied_cells(&state),
+            vec![
+                SandboxCoordinate { x: 1, y: 1 },
+                SandboxCoordinate { x: 4, y: 2 }
+            ]
+        );
+        // and turning gravity back on lets them fall again
+        state.parameters.gravity.toggle_zero_g();
+        for _ in 0..10 {
+            state.update_atoms();
+        }
+        assert!(occupied_cells(&state).iter().all(|coord| coord.y == 5));
+    }
+
+    #[test]
+    fn gas_wanders_without_sinking_in_zero_g() {
+        // a cloud of smoke in the middle of a weightless sandbox spreads out, but stays centred
+        // where it started
+        let mut state = State::new(40, 40);
+        state.set_seed(5);
+        state.parameters.gravity.zero_g = true;
+        state.set_active_tool(Tool::Element(el("smoke")));
+        for y in 18..22 {
+            for x in 18..22 {
+                state.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        for _ in 0..40 {
+            state.update_atoms();
+        }
+        let cells = occupied_cells(&state);
+        assert_eq!(cells.len(), 16);
+        let centre = |axis: fn(&SandboxCoordinate) -> i32| {
+            cells.iter().map(axis).sum::<i32>() as f32 / cells.len() as f32
+        };
+        assert!((centre(|coord| coord.x) - 19.5).abs() <= 2.0);
+        assert!((centre(|coord| coord.y) - 19.5).abs() <= 2.0);
+    }
+
+    #[test]
+    fn gas_rises_and_fills_its_container() {
+        // smoke let off at the bottom of a closed box floats to the top, then spreads out
+        // underneath the lid
+        let mut state = State::new(8, 6);
+        state.set_seed(3);
+        state.set_active_tool(Tool::Element(el("smoke")));
+        for x in 0..4 {
+            state.make_atom(SandboxCoordinate { x, y: 5 }).unwrap();
+        }
+        for _ in 0..20 {
+            state.update_atoms();
+        }
+        let gas_cells = occupied_cells(&state);
+        assert_eq!(gas_cells.len(), 4);
+        assert!(gas_cells.iter().all(|coord| coord.y < 5));
+        assert!(gas_cells.iter().any(|coord| coord.x >= 4));
+    }
+
+    #[test]
+    fn wind_blows_gas_along() {
+        // the same puff of smoke, let off in still air and in a wind blowing to the right
+        let mean_x = |wind: bool| {
+            let mut state = State::new(24, 8);
+            state.set_seed(7);
+            state.set_active_tool(Tool::Element(el("smoke")));
+            for x in 2..6 {
+                state.make_atom(SandboxCoordinate { x, y: 7 }).unwrap();
+            }
+            state.set_active_tool(Tool::Wind);
+            state.brush.radius = 0;
+            for _ in 0..30 {
+                if wind {
+                    for x in 0..24 {
+                        for y in 0..8 {
+                            state.use_active_tool(
+                                SandboxCoordinate { x, y },
+                                SandboxCoordinate { x: 1, y: 0 },
+                            );
+                        }
+                    }
+                }
+                state.update_atoms();
+            }
+            let cells = occupied_cells(&state);
+            cells.iter().map(|coord| coord.x).sum::<i32>() as f32 / cells.len() as f32
+        };
+        assert!(mean_x(true) > mean_x(false) + 4.0);
+    }
+
+    #[test]
+    fn pressure_tool_only_touches_the_air() {
+        let mut state = State::new(8, 8);
+        state.set_active_tool(Tool::Pressure);
+        state.use_active_tool(SandboxCoordinate { x: 4, y: 4 }, STILL);
+        assert_eq!(state.atoms().count(), 0);
+        assert!(state.air().cells().any(|(_, pressure, _)| pressure > 0.0));
+    }
+
+    #[test]
+    fn atoms_disappear_at_the_end_of_their_lifetime() {
+        let mut state = State::new(4, 4);
+        state.set_active_tool(Tool::Element(el("smoke")));
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).unwrap();
+        let lifetime = el("smoke").properties().lifetime.unwrap();
+        for _ in 0..lifetime {
+            state.update_atoms();
+        }
+        assert_eq!(state.atoms().count(), 0);
+    }
+
+    #[test]
+    fn liquid_sinks_through_gas() {
+        let mut state = State::new(1, 3);
+        state.set_active_tool(Tool::Element(el("steam")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 2 }).unwrap();
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 1 }).unwrap();
+        for _ in 0..3 {
+            state.update_atoms();
+        }
+        assert_eq!(element_at(&state, 0, 2), Some(el("water")));
+    }
+
+    fn count(state: &State, element: Element) -> usize {
+        state
+            .atoms()
+            .filter(|(_, atom)| atom.element() == element)
+            .count()
+    }
+
+    #[test]
+    fn fire_spreads_along_plants_and_leaves_ash() {
+        // lava at one end of a row of plants sets the first alight, which sets the next alight,
+        // and so on, with a stone at the other end that doesn't burn at all
+        let mut state = State::new(10, 4);
+        state.set_seed(2);
+        state.set_active_tool(Tool::Element(el("lava")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 3 }).unwrap();
+        state.set_active_tool(Tool::Element(el("plant")));
+        for x in 1..9 {
+            state.make_atom(SandboxCoordinate { x, y: 3 }).unwrap();
+        }
+        state.set_active_tool(Tool::Element(el("stone")));
+        state.make_atom(SandboxCoordinate { x: 9, y: 3 }).unwrap();
+        for _ in 0..400 {
+            state.update_atoms();
+        }
+        assert_eq!(count(&state, el("plant")), 0);
+        assert!(count(&state, el("ash")) > 0);
+        assert_eq!(element_at(&state, 9, 3), Some(el("stone")));
+    }
+
+    #[test]
+    fn burning_atoms_give_off_smoke_against_gravity() {
+        // with gravity pulling to the right, flames and smoke come off the left of a burning
+        // plant, not the top
+        let mut state = State::new(10, 10);
+        state.set_seed(3);
+        state.parameters.gravity.direction = Direction::Right;
+        state.set_active_tool(Tool::Element(el("plant")));
+        state.make_atom(SandboxCoordinate { x: 5, y: 5 }).unwrap();
+        if let Some(atom) = state
+            .cells
+            .get_cell_contents_mut(SandboxCoordinate { x: 5, y: 5 })
+        {
+            atom.burning = Some(40);
+        }
+        // products sit still on the tick they're made, so the first ones are still where they
+        // were put
+        let products = (0..30)
+            .find_map(|_| {
+                state.update_atoms();
+                let products: Vec<SandboxCoordinate> = state
+                    .atoms()
+                    .filter(|(_, atom)| atom.element() != el("plant"))
+                    .map(|(coord, _)| coord)
+                    .collect();
+                (!products.is_empty()).then_some(products)
+            })
+            .expect("Nothing given off");
+        assert!(products.iter().all(|coord| coord.x == 4));
+    }
+
+    #[test]
+    fn gunpowder_goes_up_with_a_bang() {
+        let mut state = State::new(8, 8);
+        state.set_seed(1);
+        state.set_active_tool(Tool::Element(el("gunpowder")));
+        for x in 2..6 {
+            state.make_atom(SandboxCoordinate { x, y: 7 }).unwrap();
+        }
+        if let Some(atom) = state
+            .cells
+            .get_cell_contents_mut(SandboxCoordinate { x: 2, y: 7 })
+        {
+            atom.burning = Some(1);
+        }
+        for _ in 0..20 {
+            state.update_atoms();
+        }
+        assert_eq!(count(&state, el("gunpowder")), 0);
+        assert!(state.air().cells().any(|(_, pressure, _)| pressure > 0.0));
+    }
+
+    #[test]
+    fn water_cools_lava_into_stone() {
+        let mut state = State::new(1, 2);
+        state.set_seed(5);
+        state.set_active_tool(Tool::Element(el("lava")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 1 }).unwrap();
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 0 }).unwrap();
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 0), Some(el("steam")));
+        assert_eq!(element_at(&state, 0, 1), Some(el("stone")));
+    }
+
+    #[test]
+    fn water_puts_out_fire() {
+        // the fire disappears, and the steam left behind floats up to where it was
+        let mut state = State::new(1, 2);
+        state.set_seed(5);
+        state.set_active_tool(Tool::Element(el("water")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 1 }).unwrap();
+        state.set_active_tool(Tool::Element(el("fire")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 0 }).unwrap();
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 0), None);
+        assert_eq!(element_at(&state, 0, 1), Some(el("steam")));
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 0), Some(el("steam")));
+        assert_eq!(element_at(&state, 0, 1), None);
+    }
+
+    #[test]
+    fn lava_melts_stone_but_not_wall() {
+        // a layer of lava on a stone shelf, with nothing around to cool it down, slowly melts
+        // its way through the stone, but sits on a wall forever
+        let mut state = State::new(3, 3);
+        state.set_seed(5);
+        for (element, y) in [("wall", 2), ("stone", 1), ("lava", 0)] {
+            state.set_active_tool(Tool::Element(el(element)));
+            for x in 0..3 {
+                state.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        for _ in 0..50 {
+            state.update_atoms();
+        }
+        assert_eq!(
+            (count(&state, el("stone")), count(&state, el("lava"))),
+            (0, 6)
+        );
+        for _ in 0..200 {
+            state.update_atoms();
+        }
+        assert_eq!(count(&state, el("wall")), 3);
+    }
+
+    fn temperature_at(state: &State, x: i32, y: i32) -> f32 {
+        state.cells.temperature(SandboxCoordinate { x, y })
+    }
+
+    #[test]
+    fn heat_travels_with_atoms() {
+        // lava dropped down a column is still hot when it lands, and leaves the air it fell
+        // through only a little warm
+        let mut state = State::new(1, 6);
+        state.set_active_tool(Tool::Element(el("lava")));
+        state.make_atom(SandboxCoordinate { x: 0, y: 0 }).unwrap();
+        for _ in 0..5 {
+            state.update_atoms();
+        }
+        assert_eq!(element_at(&state, 0, 5), Some(el("lava")));
+        assert!(temperature_at(&state, 0, 5) > 1000.0);
+        assert!(temperature_at(&state, 0, 0) < 100.0);
+    }
+
+    #[test]
+    fn elements_change_state_at_their_thresholds() {
+        // three drops of water kept apart by walls, so they can't flow into each other
+        let mut state = State::new(5, 1);
+        for (element, x) in [
+            ("water", 0),
+            ("wall", 1),
+            ("water", 2),
+            ("wall", 3),
+            ("water", 4),
+        ] {
+            state.set_active_tool(Tool::Element(el(element)));
+            state.make_atom(SandboxCoordinate { x, y: 0 }).unwrap();
+        }
+        state
+            .cells
+            .set_temperature(SandboxCoordinate { x: 0, y: 0 }, -50.0);
+        state
+            .cells
+            .set_temperature(SandboxCoordinate { x: 4, y: 0 }, 150.0);
+        state.update_atoms();
+        assert_eq!(element_at(&state, 0, 0), Some(el("ice")));
+        assert_eq!(element_at(&state, 2, 0), Some(el("water")));
+        assert_eq!(element_at(&state, 4, 0), Some(el("steam")));
+    }
+
+    #[test]
+    fn sand_in_lava_turns_to_glass() {
+        let mut state = State::new(3, 3);
+        state.set_active_tool(Tool::Element(el("lava")));
+        for x in 0..3 {
+            for y in 0..3 {
+                state.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        state.remove_atom(SandboxCoordinate { x: 1, y: 2 }).unwrap();
+        state.set_active_tool(Tool::Element(el("sand")));
+        state.make_atom(SandboxCoordinate { x: 1, y: 2 }).unwrap();
+        for _ in 0..100 {
+            state.update_atoms();
+        }
+        assert_eq!(count(&state, el("glass")), 1);
+    }
+
+    fn settled_sand(w: i32, h: i32) -> State {
+        // a floor of sand four cells deep, which has nowhere to go
+        let mut state = State::new(w, h);
+        for y in h - 4..h {
+            for x in 0..w {
+                state.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        state.update_atoms();
+        state.update_atoms();
+        state
+    }
+
+    #[test]
+    fn settled_chunks_sleep_until_something_wakes_them() {
+        let mut state = settled_sand(64, 32);
+        assert_eq!(state.active_chunks().count(), 0);
+        // digging a hole in the right hand chunk wakes just that chunk, and the sand above the
+        // hole falls into it
+        state
+            .remove_atom(SandboxCoordinate { x: 40, y: 31 })
+            .unwrap();
+        state.update_atoms();
+        let active: Vec<usize> = state.active_chunks().map(|chunk| chunk.index).collect();
+        assert_eq!(active, vec![1]);
+        for _ in 0..10 {
+            state.update_atoms();
+        }
+        assert!(occupied(&state, 40, 31));
+        assert_eq!(state.active_chunks().count(), 0);
+    }
+
+    #[test]
+    fn asleep_sandbox_is_left_alone() {
+        // with every chunk asleep nothing is conducted, changes state or blows about, even
+        // where it would if it were awake
+        let mut state = settled_sand(64, 32);
+        assert_eq!(state.active_chunks().count(), 0);
+        let hot = SandboxCoordinate { x: 8, y: 28 };
+        state.cells.set_temperature(hot, 2000.0);
+        state.air.set(hot, 5.0, Vec2::new(1.0, 0.0));
+        for _ in 0..10 {
+            state.update_atoms();
+        }
+        assert_eq!(
+            state.cells.get_cell_contents(hot).unwrap().element(),
+            el("sand")
+        );
+        assert_eq!(state.cells.temperature(hot), 2000.0);
+        assert_eq!(
+            state.cells.temperature(SandboxCoordinate { x: 9, y: 28 }),
+            heat::AMBIENT_TEMPERATURE
+        );
+        assert!(state
+            .air
+            .cells()
+            .all(|(coord, pressure, _)| pressure == if coord == hot { 5.0 } else { 0.0 }));
+    }
+
+    #[test]
+    fn settled_puddles_sleep() {
+        // once a block of water has spread out across the floor it stops flowing about on top
+        // of itself, and its chunks go to sleep
+        let mut state = State::new(64, 32);
+        state.set_active_tool(Tool::Element(el("water")));
+        for y in 16..32 {
+            for x in 28..36 {
+                state.make_atom(SandboxCoordinate { x, y }).unwrap();
+            }
+        }
+        for _ in 0..300 {
+            state.update_atoms();
+        }
+        assert_eq!(state.active_chunks().count(), 0);
+        assert!(occupied_cells(&state).iter().all(|coord| coord.y >= 29));
+    }
+
+    #[test]
+    fn turning_gravity_wakes_everything() {
+        let mut state = settled_sand(64, 32);
+        state.parameters.gravity.rotate(2);
+        state.update_atoms();
+        assert_eq!(state.active_chunks().count(), 2);
+    }
 }
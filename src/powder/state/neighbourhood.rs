@@ -0,0 +1,77 @@
+use super::atom::Element;
+
+// what an atom can see in a neighbouring cell
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Neighbour {
+    Empty,
+    Atom(Element),
+    OutOfBounds,
+}
+
+// The 8 cells around an atom, stored row by row: the three above it from left to right, then
+// left and right of it, then the three below it from left to right.
+#[derive(Copy, Clone, Debug)]
+pub struct Neighbourhood([Neighbour; 8]);
+
+impl Neighbourhood {
+    pub const OFFSETS: [(i32, i32); 8] = [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ];
+
+    pub fn new(neighbour_at: impl Fn(i32, i32) -> Neighbour) -> Self {
+        // neighbour_at(dx, dy) says what's in the cell offset by (dx, dy) from the atom
+        Neighbourhood(Self::OFFSETS.map(|(dx, dy)| neighbour_at(dx, dy)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32), Neighbour)> + '_ {
+        // each neighbour along with its offset from the atom
+        Self::OFFSETS.iter().copied().zip(self.0.iter().copied())
+    }
+
+    pub fn above_left(&self) -> Neighbour {
+        self.0[0]
+    }
+
+    pub fn above(&self) -> Neighbour {
+        self.0[1]
+    }
+
+    pub fn above_right(&self) -> Neighbour {
+        self.0[2]
+    }
+
+    pub fn left(&self) -> Neighbour {
+        self.0[3]
+    }
+
+    pub fn right(&self) -> Neighbour {
+        self.0[4]
+    }
+
+    pub fn below_left(&self) -> Neighbour {
+        self.0[5]
+    }
+
+    pub fn below(&self) -> Neighbour {
+        self.0[6]
+    }
+
+    pub fn below_right(&self) -> Neighbour {
+        self.0[7]
+    }
+
+    pub fn above_row(&self) -> &[Neighbour] {
+        &self.0[..3]
+    }
+
+    pub fn sides_and_below(&self) -> &[Neighbour] {
+        &self.0[3..]
+    }
+}
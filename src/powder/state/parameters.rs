@@ -1,9 +1,121 @@
-#[derive(Debug)]
-pub struct ParameterError;
+// Which way gravity pulls, as one of the 8 neighbouring cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+    Up,
+    UpRight,
+    Right,
+    DownRight,
+}
+
+impl Direction {
+    // going clockwise round from straight down
+    const CLOCKWISE: [Direction; 8] = [
+        Direction::Down,
+        Direction::DownLeft,
+        Direction::Left,
+        Direction::UpLeft,
+        Direction::Up,
+        Direction::UpRight,
+        Direction::Right,
+        Direction::DownRight,
+    ];
+
+    pub fn turns(self) -> i32 {
+        // how many eighths of a turn clockwise this is from straight down
+        Self::CLOCKWISE.iter().position(|&d| d == self).unwrap() as i32
+    }
 
-impl std::fmt::Display for ParameterError {
+    pub fn rotated(self, eighths: i32) -> Self {
+        // eighths of a turn clockwise, or anticlockwise if negative
+        Self::CLOCKWISE[(self.turns() + eighths).rem_euclid(8) as usize]
+    }
+}
+
+impl std::fmt::Display for Direction {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Couldn't make this parameter change")
+        let name = match self {
+            Direction::Down => "down",
+            Direction::DownLeft => "down-left",
+            Direction::Left => "left",
+            Direction::UpLeft => "up-left",
+            Direction::Up => "up",
+            Direction::UpRight => "up-right",
+            Direction::Right => "right",
+            Direction::DownRight => "down-right",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Movement rules are all written as if gravity pulled straight down; orient turns a move worked
+// out that way into one in the sandbox, whichever way gravity actually points.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gravity {
+    pub direction: Direction,
+    // scales how quickly falling atoms pick up speed; 1 is normal
+    pub strength: f32,
+    // while set, nothing falls (and gases don't rise), whatever the strength
+    pub zero_g: bool,
+}
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Gravity {
+            direction: Direction::Down,
+            strength: 1.0,
+            zero_g: false,
+        }
+    }
+}
+
+impl Gravity {
+    pub fn acceleration(&self) -> f32 {
+        if self.zero_g {
+            0.0
+        } else {
+            self.strength
+        }
+    }
+
+    pub fn rotate(&mut self, eighths: i32) {
+        self.direction = self.direction.rotated(eighths);
+    }
+
+    pub fn toggle_zero_g(&mut self) {
+        self.zero_g = !self.zero_g;
+    }
+
+    pub fn orient(&self, dx: i32, dy: i32) -> (i32, i32) {
+        // turns an offset relative to gravity, where +y is the way gravity pulls, into one in
+        // the sandbox. offsets are turned round the square ring of cells they sit on rather
+        // than through a true angle, so that every cell maps onto exactly one other and a
+        // neighbour stays a neighbour
+        let turns = self.direction.turns();
+        let (mut x, mut y) = (dx, dy);
+        for _ in 0..turns / 2 {
+            // a quarter turn clockwise
+            (x, y) = (-y, x);
+        }
+        if turns % 2 == 1 {
+            // an eighth of a turn is as many steps clockwise round the ring as it's far out
+            let r = x.abs().max(y.abs());
+            for _ in 0..r {
+                if y == -r && x < r {
+                    x += 1;
+                } else if x == r && y < r {
+                    y += 1;
+                } else if y == r && x > -r {
+                    x -= 1;
+                } else {
+                    y -= 1;
+                }
+            }
+        }
+        (x, y)
     }
 }
 
@@ -11,19 +123,57 @@ impl std::fmt::Display for ParameterError {
 pub struct Parameters {
     pub sandbox_w: i32,
     pub sandbox_h: i32,
+    pub gravity: Gravity,
+    // how many threads to sweep the sandbox with; 1 sweeps it on the calling thread
+    pub threads: usize,
 }
 
 impl Parameters {
-    pub fn new(sandbox_size: i32) -> Self {
-        let sandbox_w = sandbox_size;
-        let sandbox_h = sandbox_size;
-        // for now things are gonna break in the renderer if this isn't square
-        // so let's just mandate that for now
-        assert_eq!(sandbox_w, sandbox_h);
+    pub fn new(sandbox_w: i32, sandbox_h: i32) -> Self {
+        assert!(sandbox_w > 0 && sandbox_h > 0);
 
         Parameters {
-            sandbox_w: sandbox_w,
-            sandbox_h: sandbox_h,
+            sandbox_w,
+            sandbox_h,
+            gravity: Gravity::default(),
+            threads: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gravity_turns_offsets_to_point_its_way() {
+        let mut gravity = Gravity::default();
+        assert_eq!(gravity.orient(0, 1), (0, 1));
+        assert_eq!(gravity.orient(3, -2), (3, -2));
+        for (direction, down) in Direction::CLOCKWISE.iter().zip([
+            (0, 1),
+            (-1, 1),
+            (-1, 0),
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+        ]) {
+            gravity.direction = *direction;
+            assert_eq!(gravity.orient(0, 1), down);
         }
+        // every cell in a ring maps to a different cell in the same ring
+        gravity.direction = Direction::UpRight;
+        let mut ring: Vec<_> = (-2..=2)
+            .flat_map(|y| (-2..=2).map(move |x| (x, y)))
+            .filter(|&(x, y): &(i32, i32)| x.abs().max(y.abs()) == 2)
+            .collect();
+        let mut turned: Vec<_> = ring.iter().map(|&(x, y)| gravity.orient(x, y)).collect();
+        ring.sort();
+        turned.sort();
+        assert_eq!(ring, turned);
+        assert_eq!(Direction::Down.rotated(-1), Direction::DownRight);
+        assert_eq!(Direction::Right.rotated(3), Direction::DownLeft);
     }
 }
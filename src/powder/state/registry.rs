@@ -0,0 +1,695 @@
+// The element registry: every element the simulation knows about and how they react with each
+// other, as described by a definitions file (see resources/elements.toml). It's installed once at
+// startup and shared from then on, so an Element only needs to carry its index into it.
+use std::path::Path;
+use std::sync::OnceLock;
+
+use ggez::graphics::Color;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::atom::Element;
+use super::heat::AMBIENT_TEMPERATURE;
+
+// compiled in so there's always something to fall back on, and so tests don't need a file
+const BUILTIN_DEFINITIONS: &str = include_str!("../../../resources/elements.toml");
+
+// what a reaction product is called when the reactant disappears instead of turning into something
+const NOTHING: &str = "empty";
+
+static REGISTRY: OnceLock<ElementRegistry> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("Couldn't read element definitions: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Couldn't parse element definitions: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Element definitions must define at least one element")]
+    Empty,
+    #[error("Too many elements defined ({0}, the most is {max})", max = u8::MAX)]
+    TooMany(usize),
+    #[error("Element {0} is defined more than once")]
+    DuplicateName(String),
+    #[error("Element {name} is invalid: {reason}")]
+    Invalid { name: String, reason: &'static str },
+    #[error("Reaction, transition or combustion refers to unknown element {0}")]
+    UnknownElement(String),
+    #[error("Reaction between {0} and {1} is invalid: {reason}", reason = .2)]
+    InvalidReaction(String, String, &'static str),
+    #[error("Element registry has already been installed")]
+    AlreadyInstalled,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatterState {
+    // never moves, and can't be displaced by anything
+    Solid,
+    Powder,
+    Liquid,
+    // rises, and wanders about once it can't rise any further
+    Gas,
+}
+
+// Everything about how an element looks and behaves; movement rules are written per state of
+// matter and tuned by these numbers, rather than per element.
+#[derive(Clone, Debug)]
+pub struct ElementProperties {
+    pub name: String,
+    pub color: Color,
+    pub state: MatterState,
+    // relative to water; a denser atom sinks through a lighter one by swapping places with it
+    pub density: f32,
+    // liquids only: chance of *not* flowing sideways on a given tick, from 0 (flows whenever it
+    // can) to 1 (never spreads out)
+    pub viscosity: f32,
+    // liquids and gases only: furthest they can flow or wander sideways in one tick
+    pub dispersion: i32,
+    // how many ticks an atom lasts before disappearing, if it doesn't last forever
+    pub lifetime: Option<u16>,
+    // can't be turned into anything else by a reaction
+    pub indestructible: bool,
+    // what temperature atoms of the element start out at
+    pub temperature: f32,
+    // how readily heat flows in and out, from 0 (not at all) to 1
+    pub conductivity: f32,
+    // how much heat it takes to warm up, relative to air
+    pub specific_heat: f32,
+    // what the element turns into once it gets hotter or colder than a given temperature
+    pub heats_into: Option<Transition>,
+    pub cools_into: Option<Transition>,
+    // sets anything flammable touching it alight
+    pub ignites: bool,
+    // how the element burns, if it's flammable
+    pub burns: Option<Combustion>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Transition {
+    pub temperature: f32,
+    pub element: Element,
+}
+
+#[derive(Clone, Debug)]
+pub struct Combustion {
+    // chance of catching fire each tick spent touching something alight
+    pub ignition: f32,
+    // how many ticks it burns for once it's caught
+    pub duration: u16,
+    // what's left once it's burnt out; None for nothing
+    pub leaves: Option<Element>,
+    // one of these, picked at random, is thrown off into the cell above every so often while it
+    // burns
+    pub gives_off: Vec<Element>,
+    // how much air pressure it lets off as it burns out
+    pub blast: f32,
+}
+
+// Two neighbouring atoms turning into something else. Each reaction is checked by atoms of the
+// first reactant as they're updated, once a tick, against each neighbour of the second.
+#[derive(Clone, Debug)]
+pub struct Reaction {
+    // chance of reacting each tick the reactants spend touching
+    pub probability: f32,
+    // what each reactant turns into, in the same order as the reactants; None for nothing
+    pub products: [Option<Element>; 2],
+}
+
+// what an element looks like in the definitions file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ElementDefinition {
+    name: String,
+    color: [u8; 3],
+    state: MatterState,
+    density: f32,
+    #[serde(default)]
+    viscosity: f32,
+    #[serde(default)]
+    dispersion: i32,
+    #[serde(default)]
+    lifetime: Option<u16>,
+    #[serde(default)]
+    indestructible: bool,
+    #[serde(default = "ambient_temperature")]
+    temperature: f32,
+    #[serde(default = "default_conductivity")]
+    conductivity: f32,
+    #[serde(default = "default_specific_heat")]
+    specific_heat: f32,
+    #[serde(default)]
+    heats_into: Option<TransitionDefinition>,
+    #[serde(default)]
+    cools_into: Option<TransitionDefinition>,
+    #[serde(default)]
+    ignites: bool,
+    #[serde(default)]
+    burns: Option<CombustionDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransitionDefinition {
+    temperature: f32,
+    element: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CombustionDefinition {
+    ignition: f32,
+    duration: u16,
+    leaves: String,
+    #[serde(default)]
+    gives_off: Vec<String>,
+    #[serde(default)]
+    blast: f32,
+}
+
+fn ambient_temperature() -> f32 {
+    AMBIENT_TEMPERATURE
+}
+
+fn default_conductivity() -> f32 {
+    0.5
+}
+
+fn default_specific_heat() -> f32 {
+    1.0
+}
+
+// what a reaction looks like in the definitions file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReactionDefinition {
+    reactants: [String; 2],
+    products: [String; 2],
+    probability: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Definitions {
+    element: Vec<ElementDefinition>,
+    #[serde(default)]
+    reaction: Vec<ReactionDefinition>,
+}
+
+impl ElementDefinition {
+    // transitions and combustion are handed back separately, to be resolved once every element
+    // is known
+    #[allow(clippy::type_complexity)]
+    fn validate(
+        self,
+    ) -> Result<
+        (
+            ElementProperties,
+            [Option<TransitionDefinition>; 2],
+            Option<CombustionDefinition>,
+        ),
+        RegistryError,
+    > {
+        let invalid = |reason| RegistryError::Invalid {
+            name: self.name.clone(),
+            reason,
+        };
+        // names are written to snapshots with a one byte length
+        if self.name.is_empty() || self.name.len() > u8::MAX as usize {
+            return Err(invalid("name must be between 1 and 255 bytes long"));
+        }
+        if self.name == NOTHING {
+            return Err(invalid("name is reserved for reaction products"));
+        }
+        if !self.density.is_finite() || self.density <= 0.0 {
+            return Err(invalid("density must be positive"));
+        }
+        if !(0.0..=1.0).contains(&self.viscosity) {
+            return Err(invalid("viscosity must be between 0 and 1"));
+        }
+        let flows = matches!(self.state, MatterState::Liquid | MatterState::Gas);
+        if flows && self.dispersion < 1 {
+            return Err(invalid(
+                "liquids and gases must have a dispersion of at least 1",
+            ));
+        }
+        if self.lifetime == Some(0) {
+            return Err(invalid("lifetime must be at least 1 tick"));
+        }
+        if !(0.0..=1.0).contains(&self.conductivity) {
+            return Err(invalid("conductivity must be between 0 and 1"));
+        }
+        if !self.specific_heat.is_finite() || self.specific_heat < 1.0 {
+            return Err(invalid("specific heat must be at least 1"));
+        }
+        if let (Some(heats), Some(cools)) = (&self.heats_into, &self.cools_into) {
+            if heats.temperature <= cools.temperature {
+                return Err(invalid(
+                    "has to get hotter to heat into something than to cool into something",
+                ));
+            }
+        }
+        if let Some(burns) = &self.burns {
+            if self.indestructible {
+                return Err(invalid("an indestructible element can't burn"));
+            }
+            if !(burns.ignition > 0.0 && burns.ignition <= 1.0) {
+                return Err(invalid("ignition must be above 0 and at most 1"));
+            }
+            if burns.duration == 0 {
+                return Err(invalid("has to burn for at least 1 tick"));
+            }
+            if !burns.blast.is_finite() || burns.blast < 0.0 {
+                return Err(invalid("blast can't be negative"));
+            }
+        }
+        let [r, g, b] = self.color;
+        Ok((
+            ElementProperties {
+                name: self.name,
+                color: Color::from_rgb(r, g, b),
+                state: self.state,
+                density: self.density,
+                viscosity: self.viscosity,
+                dispersion: self.dispersion,
+                lifetime: self.lifetime,
+                indestructible: self.indestructible,
+                temperature: self.temperature,
+                conductivity: self.conductivity,
+                specific_heat: self.specific_heat,
+                heats_into: None,
+                cools_into: None,
+                ignites: self.ignites,
+                burns: None,
+            },
+            [self.heats_into, self.cools_into],
+            self.burns,
+        ))
+    }
+}
+
+impl ReactionDefinition {
+    fn validate(
+        self,
+        elements: &[ElementProperties],
+    ) -> Result<(usize, usize, Reaction), RegistryError> {
+        let [a, b] = &self.reactants;
+        let invalid = |reason| RegistryError::InvalidReaction(a.clone(), b.clone(), reason);
+        let find = |name: &String| {
+            elements
+                .iter()
+                .position(|el| &el.name == name)
+                .ok_or_else(|| RegistryError::UnknownElement(name.clone()))
+        };
+        let reactants = [find(a)?, find(b)?];
+        let mut products = [None, None];
+        for (product, name) in products.iter_mut().zip(self.products.iter()) {
+            if name != NOTHING {
+                *product = Some(find(name)?);
+            }
+        }
+        if !(self.probability > 0.0 && self.probability <= 1.0) {
+            return Err(invalid("probability must be above 0 and at most 1"));
+        }
+        // the first reactant is the one that goes looking for the second, and solids don't
+        // look around them at all
+        if elements[reactants[0]].state == MatterState::Solid {
+            return Err(invalid("the first reactant can't be a solid"));
+        }
+        for (&reactant, &product) in reactants.iter().zip(products.iter()) {
+            if elements[reactant].indestructible && product != Some(reactant) {
+                return Err(invalid(
+                    "an indestructible reactant has to come out unchanged",
+                ));
+            }
+        }
+        Ok((
+            reactants[0],
+            reactants[1],
+            Reaction {
+                probability: self.probability,
+                products: products.map(|product| product.map(|id| Element::from_id(id as u8))),
+            },
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub struct ElementRegistry {
+    // indexed by Element
+    elements: Vec<ElementProperties>,
+    // indexed by the first reactant's id times the number of elements plus the second's, so
+    // finding the reaction for a pair is a single lookup
+    reactions: Vec<Option<Reaction>>,
+}
+
+impl Default for ElementRegistry {
+    fn default() -> Self {
+        ElementRegistry::from_toml(BUILTIN_DEFINITIONS).expect("Built-in element definitions")
+    }
+}
+
+impl ElementRegistry {
+    pub fn from_toml(definitions: &str) -> Result<Self, RegistryError> {
+        let definitions: Definitions = toml::from_str(definitions)?;
+        if definitions.element.is_empty() {
+            return Err(RegistryError::Empty);
+        }
+        // ids have to fit in a u8, and so does the snapshot palette length
+        if definitions.element.len() > u8::MAX as usize {
+            return Err(RegistryError::TooMany(definitions.element.len()));
+        }
+        let mut elements: Vec<ElementProperties> = vec![];
+        let mut unresolved = vec![];
+        for definition in definitions.element {
+            if elements.iter().any(|el| el.name == definition.name) {
+                return Err(RegistryError::DuplicateName(definition.name));
+            }
+            let (properties, transitions, burns) = definition.validate()?;
+            elements.push(properties);
+            unresolved.push((transitions, burns));
+        }
+        for (id, ([heats_into, cools_into], burns)) in unresolved.into_iter().enumerate() {
+            let find = |name: String| {
+                elements
+                    .iter()
+                    .position(|el| el.name == name)
+                    .map(|id| Element::from_id(id as u8))
+                    .ok_or(RegistryError::UnknownElement(name))
+            };
+            let resolve = |transition: Option<TransitionDefinition>| -> Result<_, RegistryError> {
+                transition
+                    .map(|transition| {
+                        Ok(Transition {
+                            temperature: transition.temperature,
+                            element: find(transition.element)?,
+                        })
+                    })
+                    .transpose()
+            };
+            let (heats_into, cools_into) = (resolve(heats_into)?, resolve(cools_into)?);
+            let burns = burns
+                .map(|burns| -> Result<_, RegistryError> {
+                    Ok(Combustion {
+                        ignition: burns.ignition,
+                        duration: burns.duration,
+                        leaves: if burns.leaves == NOTHING {
+                            None
+                        } else {
+                            Some(find(burns.leaves)?)
+                        },
+                        gives_off: burns
+                            .gives_off
+                            .into_iter()
+                            .map(find)
+                            .collect::<Result<_, _>>()?,
+                        blast: burns.blast,
+                    })
+                })
+                .transpose()?;
+            elements[id].heats_into = heats_into;
+            elements[id].cools_into = cools_into;
+            elements[id].burns = burns;
+        }
+        let n = elements.len();
+        let mut reactions = vec![None; n * n];
+        for definition in definitions.reaction {
+            let (a, b, reaction) = definition.validate(&elements)?;
+            if reactions[a * n + b].is_some() || reactions[b * n + a].is_some() {
+                return Err(RegistryError::InvalidReaction(
+                    elements[a].name.clone(),
+                    elements[b].name.clone(),
+                    "it's defined more than once",
+                ));
+            }
+            reactions[a * n + b] = Some(reaction);
+        }
+        Ok(ElementRegistry {
+            elements,
+            reactions,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, RegistryError> {
+        ElementRegistry::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn install(self) -> Result<(), RegistryError> {
+        // has to happen before anything looks up an element, or the built-in definitions will
+        // have been installed instead
+        REGISTRY
+            .set(self)
+            .map_err(|_| RegistryError::AlreadyInstalled)
+    }
+
+    pub fn global() -> &'static ElementRegistry {
+        REGISTRY.get_or_init(ElementRegistry::default)
+    }
+
+    pub fn get(&self, element: Element) -> &ElementProperties {
+        &self.elements[element.id() as usize]
+    }
+
+    pub fn reaction(&self, first: Element, second: Element) -> Option<&Reaction> {
+        let n = self.elements.len();
+        self.reactions[first.id() as usize * n + second.id() as usize].as_ref()
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = Element> {
+        (0..self.elements.len()).map(|id| Element::from_id(id as u8))
+    }
+
+    pub fn find(&self, name: &str) -> Option<Element> {
+        self.elements
+            .iter()
+            .position(|el| el.name == name)
+            .map(|id| Element::from_id(id as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_definitions_load() {
+        let registry = ElementRegistry::default();
+        let water = registry.find("water").unwrap();
+        assert_eq!(registry.get(water).state, MatterState::Liquid);
+        assert_eq!(registry.get(water).color, Color::from_rgb(0, 0, 255));
+        assert_eq!(registry.elements().count(), registry.elements.len());
+        assert!(registry.find("unobtainium").is_none());
+        // reactions are only looked up from the first reactant's side
+        let lava = registry.find("lava").unwrap();
+        let reaction = registry.reaction(water, lava).unwrap();
+        assert_eq!(
+            reaction.products,
+            [registry.find("steam"), registry.find("stone")]
+        );
+        assert!(registry.reaction(lava, water).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_reactions() {
+        let definitions = |reaction: &str| {
+            format!(
+                r#"
+                [[element]]
+                name = "acid"
+                color = [0, 255, 0]
+                state = "liquid"
+                density = 1.0
+                dispersion = 1
+
+                [[element]]
+                name = "rock"
+                color = [90, 90, 90]
+                state = "solid"
+                density = 2.0
+
+                [[element]]
+                name = "wall"
+                color = [128, 128, 128]
+                state = "solid"
+                density = 3.0
+                indestructible = true
+
+                [[reaction]]
+                {}
+                "#,
+                reaction
+            )
+        };
+        let parse = |reaction| ElementRegistry::from_toml(&definitions(reaction));
+        assert!(parse(
+            r#"reactants = ["acid", "rock"]
+            products = ["empty", "empty"]
+            probability = 0.5"#
+        )
+        .is_ok());
+        assert!(matches!(
+            parse(
+                r#"reactants = ["acid", "glass"]
+                products = ["empty", "empty"]
+                probability = 0.5"#
+            ),
+            Err(RegistryError::UnknownElement(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"reactants = ["rock", "acid"]
+                products = ["empty", "empty"]
+                probability = 0.5"#
+            ),
+            Err(RegistryError::InvalidReaction(..))
+        ));
+        assert!(matches!(
+            parse(
+                r#"reactants = ["acid", "wall"]
+                products = ["empty", "empty"]
+                probability = 0.5"#
+            ),
+            Err(RegistryError::InvalidReaction(..))
+        ));
+        assert!(matches!(
+            parse(
+                r#"reactants = ["acid", "rock"]
+                products = ["empty", "empty"]
+                probability = 0.0"#
+            ),
+            Err(RegistryError::InvalidReaction(..))
+        ));
+    }
+
+    #[test]
+    fn parses_definitions_with_defaults() {
+        let registry = ElementRegistry::from_toml(
+            r#"
+            [[element]]
+            name = "gravel"
+            color = [90, 90, 90]
+            state = "powder"
+            density = 2.0
+            "#,
+        )
+        .unwrap();
+        let gravel = registry.get(registry.find("gravel").unwrap());
+        assert_eq!(gravel.state, MatterState::Powder);
+        assert_eq!((gravel.viscosity, gravel.dispersion), (0.0, 0));
+        assert_eq!(gravel.lifetime, None);
+        assert_eq!(gravel.temperature, AMBIENT_TEMPERATURE);
+        assert!(gravel.heats_into.is_none());
+    }
+
+    #[test]
+    fn parses_transitions() {
+        let registry = ElementRegistry::default();
+        let water = registry.get(registry.find("water").unwrap());
+        let boils = water.heats_into.unwrap();
+        assert_eq!(boils.temperature, 100.0);
+        assert_eq!(Some(boils.element), registry.find("steam"));
+        assert_eq!(water.cools_into.map(|t| t.element), registry.find("ice"));
+
+        let element = |transitions: &str| {
+            ElementRegistry::from_toml(&format!(
+                "[[element]]\nname = \"a\"\ncolor = [0, 0, 0]\nstate = \"powder\"\n\
+                 density = 1.0\n{}\n",
+                transitions
+            ))
+        };
+        assert!(matches!(
+            element("heats_into = { temperature = 10.0, element = \"b\" }"),
+            Err(RegistryError::UnknownElement(_))
+        ));
+        assert!(matches!(
+            element(
+                "heats_into = { temperature = 10.0, element = \"a\" }\n\
+                 cools_into = { temperature = 20.0, element = \"a\" }"
+            ),
+            Err(RegistryError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn parses_combustion() {
+        let registry = ElementRegistry::default();
+        let wood = registry.get(registry.find("wood").unwrap());
+        let burns = wood.burns.as_ref().unwrap();
+        assert_eq!(burns.leaves, registry.find("ash"));
+        assert_eq!(
+            burns.gives_off,
+            vec![
+                registry.find("fire").unwrap(),
+                registry.find("smoke").unwrap()
+            ]
+        );
+        assert!(registry.get(registry.find("fire").unwrap()).ignites);
+        assert!(registry.get(registry.find("sand").unwrap()).burns.is_none());
+
+        let element = |fields: &str| {
+            ElementRegistry::from_toml(&format!(
+                "[[element]]\nname = \"a\"\ncolor = [0, 0, 0]\nstate = \"solid\"\n\
+                 density = 1.0\n{}\n",
+                fields
+            ))
+        };
+        assert!(element("burns = { ignition = 0.5, duration = 10, leaves = \"empty\" }").is_ok());
+        assert!(matches!(
+            element("burns = { ignition = 0.5, duration = 10, leaves = \"b\" }"),
+            Err(RegistryError::UnknownElement(_))
+        ));
+        assert!(matches!(
+            element(
+                "burns = { ignition = 0.5, duration = 10, leaves = \"a\", gives_off = [\"b\"] }"
+            ),
+            Err(RegistryError::UnknownElement(_))
+        ));
+        assert!(matches!(
+            element("burns = { ignition = 0.0, duration = 10, leaves = \"a\" }"),
+            Err(RegistryError::Invalid { .. })
+        ));
+        assert!(matches!(
+            element("burns = { ignition = 0.5, duration = 0, leaves = \"a\" }"),
+            Err(RegistryError::Invalid { .. })
+        ));
+        assert!(matches!(
+            element(
+                "indestructible = true\n\
+                 burns = { ignition = 0.5, duration = 10, leaves = \"a\" }"
+            ),
+            Err(RegistryError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_definitions() {
+        let element = |fields: &str| format!("[[element]]\nname = \"a\"\n{}\n", fields);
+        let sand = element("color = [0, 0, 0]\nstate = \"powder\"\ndensity = 1.0");
+        assert!(matches!(
+            ElementRegistry::from_toml(""),
+            Err(RegistryError::Parse(_))
+        ));
+        assert!(matches!(
+            ElementRegistry::from_toml(&(sand.clone() + &sand)),
+            Err(RegistryError::DuplicateName(_))
+        ));
+        assert!(matches!(
+            ElementRegistry::from_toml(&element(
+                "color = [0, 0, 0]\nstate = \"liquid\"\ndensity = 1.0"
+            )),
+            Err(RegistryError::Invalid { .. })
+        ));
+        assert!(matches!(
+            ElementRegistry::from_toml(&element(
+                "color = [0, 0, 0]\nstate = \"powder\"\ndensity = 0.0"
+            )),
+            Err(RegistryError::Invalid { .. })
+        ));
+        assert!(matches!(
+            ElementRegistry::from_toml(&element(
+                "color = [0, 0, 0]\nstate = \"plasma\"\ndensity = 1.0"
+            )),
+            Err(RegistryError::Parse(_))
+        ));
+    }
+}
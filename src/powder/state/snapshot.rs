@@ -0,0 +1,330 @@
+// Saving and loading sandboxes. A snapshot is little-endian throughout:
+//
+//     b"PWDR", version: u16, width: u32, height: u32, seed: u64, clock: u32,
+//     gravity direction: u8 (eighths of a turn clockwise from down), gravity strength: f32,
+//     zero gravity: u8, palette length: u8, then for each palette entry: name length: u8, name bytes,
+//     then cells in the same order as Cells keeps them, each either
+//         0, run length: u32                  (a run of empty cells)
+//         palette index + 1, clock: u32, lifetime: u16, burning: u16, velocity x: f32,
+//         velocity y: f32                     (an atom; a lifetime of 0 means it lasts forever,
+//                                             and burning is 0 unless it's alight)
+//     then the temperature of every cell, in the same order, as f32
+//     then for each air cell, row by row: pressure: f32, velocity x: f32, velocity y: f32
+//
+// Element names are stored in the palette rather than relying on their ids, so snapshots
+// survive elements being added or reordered. The RNG is reseeded from the saved seed on load
+// rather than resuming where it left off.
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use glam::Vec2;
+use thiserror::Error;
+
+use super::{
+    Air, Atom, Cells, Direction, Element, ElementRegistry, Gravity, SandboxCoordinate, State,
+};
+
+const MAGIC: &[u8; 4] = b"PWDR";
+const VERSION: u16 = 8;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Couldn't read or write snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a powder snapshot")]
+    BadMagic,
+    #[error("Snapshot version {0} isn't supported (expected {VERSION})")]
+    UnsupportedVersion(u16),
+    #[error("Snapshot is {found_w}x{found_h} but the sandbox is {w}x{h}")]
+    SizeMismatch {
+        w: i32,
+        h: i32,
+        found_w: i32,
+        found_h: i32,
+    },
+    #[error("Snapshot contains unknown element {0}")]
+    UnknownElement(String),
+    #[error("Snapshot is corrupt: {0}")]
+    Corrupt(String),
+}
+
+pub fn save(state: &State, writer: &mut impl Write) -> Result<(), SnapshotError> {
+    let palette: Vec<Element> = ElementRegistry::global().elements().collect();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(state.parameters.sandbox_w as u32).to_le_bytes())?;
+    writer.write_all(&(state.parameters.sandbox_h as u32).to_le_bytes())?;
+    writer.write_all(&state.seed.to_le_bytes())?;
+    let gravity = state.parameters.gravity;
+    writer.write_all(&state.clock.to_le_bytes())?;
+    writer.write_all(&[gravity.direction.turns() as u8])?;
+    writer.write_all(&gravity.strength.to_le_bytes())?;
+    writer.write_all(&[gravity.zero_g as u8, palette.len() as u8])?;
+    for el in palette.iter() {
+        let name = el.to_string();
+        writer.write_all(&[name.len() as u8])?;
+        writer.write_all(name.as_bytes())?;
+    }
+
+    let mut empty_run: u32 = 0;
+    for y in 0..state.parameters.sandbox_h {
+        for x in 0..state.parameters.sandbox_w {
+            match state.cells.get_cell_contents(SandboxCoordinate { x, y }) {
+                None => empty_run += 1,
+                Some(atom) => {
+                    write_empty_run(writer, &mut empty_run)?;
+                    let index = palette
+                        .iter()
+                        .position(|&el| el == atom.element())
+                        .expect("Element missing from palette");
+                    writer.write_all(&[index as u8 + 1])?;
+                    writer.write_all(&atom.clock.to_le_bytes())?;
+                    writer.write_all(&atom.lifetime.unwrap_or(0).to_le_bytes())?;
+                    writer.write_all(&atom.burning.unwrap_or(0).to_le_bytes())?;
+                    writer.write_all(&atom.velocity.x.to_le_bytes())?;
+                    writer.write_all(&atom.velocity.y.to_le_bytes())?;
+                }
+            }
+        }
+    }
+    write_empty_run(writer, &mut empty_run)?;
+    for (_, temperature) in state.cells.temperatures() {
+        writer.write_all(&temperature.to_le_bytes())?;
+    }
+    for (_, pressure, velocity) in state.air.cells() {
+        writer.write_all(&pressure.to_le_bytes())?;
+        writer.write_all(&velocity.x.to_le_bytes())?;
+        writer.write_all(&velocity.y.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_empty_run(writer: &mut impl Write, empty_run: &mut u32) -> Result<(), SnapshotError> {
+    if *empty_run > 0 {
+        writer.write_all(&[0])?;
+        writer.write_all(&empty_run.to_le_bytes())?;
+        *empty_run = 0;
+    }
+    Ok(())
+}
+
+pub fn load(state: &mut State, reader: &mut impl Read) -> Result<(), SnapshotError> {
+    // everything is read into fresh Cells and Air first, so a bad snapshot leaves state untouched
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = u16::from_le_bytes(read_bytes(reader)?);
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let (w, h) = (state.parameters.sandbox_w, state.parameters.sandbox_h);
+    let found_w = u32::from_le_bytes(read_bytes(reader)?) as i32;
+    let found_h = u32::from_le_bytes(read_bytes(reader)?) as i32;
+    if (found_w, found_h) != (w, h) {
+        return Err(SnapshotError::SizeMismatch {
+            w,
+            h,
+            found_w,
+            found_h,
+        });
+    }
+    let seed = u64::from_le_bytes(read_bytes(reader)?);
+    let clock = u32::from_le_bytes(read_bytes(reader)?);
+    let [direction] = read_bytes(reader)?;
+    let strength = f32::from_le_bytes(read_bytes(reader)?);
+    let [zero_g, palette_len] = read_bytes(reader)?;
+    if direction >= 8 {
+        return Err(SnapshotError::Corrupt(format!(
+            "gravity direction {}",
+            direction
+        )));
+    }
+    let gravity = Gravity {
+        direction: Direction::Down.rotated(direction as i32),
+        strength,
+        zero_g: zero_g != 0,
+    };
+
+    let mut palette = vec![];
+    for _ in 0..palette_len {
+        let [name_len] = read_bytes(reader)?;
+        let mut name = vec![0; name_len as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        match Element::named(&name) {
+            Some(el) => palette.push(el),
+            None => return Err(SnapshotError::UnknownElement(name)),
+        }
+    }
+
+    let mut cells = Cells::new(w, h);
+    let cell_count = (w * h) as usize;
+    let mut i = 0;
+    while i < cell_count {
+        let [tag] = read_bytes(reader)?;
+        if tag == 0 {
+            let run = u32::from_le_bytes(read_bytes(reader)?) as usize;
+            if run == 0 || i + run > cell_count {
+                return Err(SnapshotError::Corrupt(String::from(
+                    "bad run of empty cells",
+                )));
+            }
+            i += run;
+        } else {
+            let el = *palette
+                .get(tag as usize - 1)
+                .ok_or_else(|| SnapshotError::Corrupt(format!("no palette entry {}", tag - 1)))?;
+            let atom_clock = u32::from_le_bytes(read_bytes(reader)?);
+            let lifetime = u16::from_le_bytes(read_bytes(reader)?);
+            let burning = u16::from_le_bytes(read_bytes(reader)?);
+            let velocity = Vec2::new(
+                f32::from_le_bytes(read_bytes(reader)?),
+                f32::from_le_bytes(read_bytes(reader)?),
+            );
+            let coord = SandboxCoordinate {
+                x: i as i32 % w,
+                y: i as i32 / w,
+            };
+            let mut atom = Atom::new(el, atom_clock);
+            atom.lifetime = (lifetime > 0).then_some(lifetime);
+            atom.burning = (burning > 0).then_some(burning);
+            atom.velocity = velocity;
+            cells.fill_cell(coord, atom).expect("Couldn't fill cell");
+            i += 1;
+        }
+    }
+    for i in 0..cell_count {
+        let coord = SandboxCoordinate {
+            x: i as i32 % w,
+            y: i as i32 / w,
+        };
+        cells.set_temperature(coord, f32::from_le_bytes(read_bytes(reader)?));
+    }
+    let mut air = Air::new(w, h);
+    let air_cells: Vec<SandboxCoordinate> = air.cells().map(|(coord, _, _)| coord).collect();
+    for coord in air_cells {
+        let pressure = f32::from_le_bytes(read_bytes(reader)?);
+        let velocity = Vec2::new(
+            f32::from_le_bytes(read_bytes(reader)?),
+            f32::from_le_bytes(read_bytes(reader)?),
+        );
+        air.set(coord, pressure, velocity);
+    }
+
+    state.cells = cells;
+    state.air = air;
+    state.clock = clock;
+    state.parameters.gravity = gravity;
+    state.set_seed(seed);
+    state.wake_all();
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], SnapshotError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn save_to_file(state: &State, path: &Path) -> Result<(), SnapshotError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    save(state, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_from_file(state: &mut State, path: &Path) -> Result<(), SnapshotError> {
+    load(state, &mut BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Tool;
+    use super::*;
+
+    fn saved(state: &State) -> Vec<u8> {
+        let mut bytes = vec![];
+        save(state, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn sandbox() -> State {
+        let mut state = State::new(6, 4);
+        state.make_atom(SandboxCoordinate { x: 1, y: 1 }).ok();
+        state.set_active_tool(Tool::Element(Element::named("water").unwrap()));
+        state.make_atom(SandboxCoordinate { x: 4, y: 0 }).ok();
+        state.update_atoms();
+        // and with gravity set some way other than the default, so it's clear it was loaded
+        state.parameters.gravity.rotate(3);
+        state.parameters.gravity.strength = 2.5;
+        state.parameters.gravity.toggle_zero_g();
+        state
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let state = sandbox();
+        let mut loaded = State::new(6, 4);
+        load(&mut loaded, &mut saved(&state).as_slice()).unwrap();
+        assert_eq!(saved(&loaded), saved(&state));
+        assert_eq!(loaded.get_seed(), state.get_seed());
+        assert_eq!(loaded.parameters.gravity, state.parameters.gravity);
+        let cells: Vec<_> = loaded
+            .atoms()
+            .map(|(coord, atom)| (coord, atom.element()))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![
+                (
+                    SandboxCoordinate { x: 4, y: 1 },
+                    Element::named("water").unwrap()
+                ),
+                (
+                    SandboxCoordinate { x: 1, y: 2 },
+                    Element::named("sand").unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lifetimes_survive_round_trip() {
+        let mut state = State::new(6, 4);
+        state.set_active_tool(Tool::Element(Element::named("smoke").unwrap()));
+        state.make_atom(SandboxCoordinate { x: 2, y: 2 }).ok();
+        let mut loaded = State::new(6, 4);
+        load(&mut loaded, &mut saved(&state).as_slice()).unwrap();
+        let lifetimes =
+            |state: &State| -> Vec<_> { state.atoms().map(|(_, atom)| atom.lifetime).collect() };
+        assert!(lifetimes(&state)[0].is_some());
+        assert_eq!(lifetimes(&loaded), lifetimes(&state));
+    }
+
+    #[test]
+    fn load_rejects_mismatched_snapshots() {
+        let bytes = saved(&sandbox());
+        let mut state = State::new(4, 6);
+        assert!(matches!(
+            load(&mut state, &mut bytes.as_slice()),
+            Err(SnapshotError::SizeMismatch { .. })
+        ));
+        let mut state = State::new(6, 4);
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 99;
+        assert!(matches!(
+            load(&mut state, &mut bad_version.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            load(&mut state, &mut &b"nope"[..]),
+            Err(SnapshotError::BadMagic)
+        ));
+        assert!(load(&mut state, &mut &bytes[..bytes.len() - 1]).is_err());
+        // failed loads leave the sandbox alone
+        assert_eq!(state.atoms().count(), 0);
+    }
+}
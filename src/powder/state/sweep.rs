@@ -0,0 +1,521 @@
+// The sweep: moving every atom once a tick. The sandbox is split into square chunks, which are
+// swept in four passes like the squares of a checkerboard, so that no two chunks swept in the
+// same pass are next to each other. Nothing an atom does reaches further than half a chunk from
+// where it is, so chunks in the same pass never touch the same cells, and they can be swept one
+// after another or all at once on separate threads with the same result. Each chunk gets its own
+// random numbers for the same reason. The chunks of a pass that share a row of the sandbox are
+// swept together, in a band of rows borrowed from the rest, and the bands of a pass are far
+// enough apart to be swept at the same time.
+//
+// Chunks where nothing happened last tick, and nothing could have, are left asleep: each sweep
+// keeps a dirty rectangle around every atom that moved, changed or still might, and only the
+// chunks in or next to one are swept again on the next tick.
+use std::ops::Range;
+
+use glam::Vec2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::air::Air;
+use super::atom::{Atom, Element};
+use super::brush;
+use super::cells::{Cells, CellsMut, Storage};
+use super::neighbourhood::{Neighbour, Neighbourhood};
+use super::parameters::Gravity;
+use super::registry::{ElementRegistry, MatterState};
+use super::SandboxCoordinate;
+
+pub const CHUNK_SIZE: i32 = 32;
+// how far round a chunk sweeping it can reach
+const MARGIN: i32 = CHUNK_SIZE / 2;
+// and so how far an atom can get in one tick; anything it looks at around where it ends up has
+// to be inside the margin too
+const MAX_REACH: i32 = MARGIN - 1;
+// chance each tick of a burning atom giving off flames or smoke
+const GIVE_OFF_CHANCE: f32 = 0.25;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Chunk {
+    // which chunk this is, counting row by row
+    pub index: usize,
+    // the top left cell, and how many cells it covers; chunks along the right and bottom edges
+    // of the sandbox can be cut short
+    pub origin: SandboxCoordinate,
+    pub w: i32,
+    pub h: i32,
+    // which of the four checkerboard passes it's swept in
+    pub pass: usize,
+}
+
+impl Chunk {
+    pub fn band(&self, sandbox_h: i32) -> Range<i32> {
+        // the rows sweeping this chunk might touch: the chunk's own and the margin above and
+        // below it, as far as the edges of the sandbox
+        (self.origin.y - MARGIN).max(0)..(self.origin.y + self.h + MARGIN).min(sandbox_h)
+    }
+
+    pub fn contains(&self, coord: SandboxCoordinate) -> bool {
+        coord.x >= self.origin.x
+            && coord.x < self.origin.x + self.w
+            && coord.y >= self.origin.y
+            && coord.y < self.origin.y + self.h
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = SandboxCoordinate> {
+        let (origin, w, h) = (self.origin, self.w, self.h);
+        (origin.y..origin.y + h)
+            .flat_map(move |y| (origin.x..origin.x + w).map(move |x| SandboxCoordinate { x, y }))
+    }
+
+    pub fn seed(&self, tick_seed: u64) -> u64 {
+        // a different seed for every chunk, every tick
+        tick_seed ^ (self.index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    // the top left and bottom right cells, inclusive
+    pub min: SandboxCoordinate,
+    pub max: SandboxCoordinate,
+}
+
+impl DirtyRect {
+    pub fn including(rect: Option<DirtyRect>, coord: SandboxCoordinate) -> DirtyRect {
+        // rect, grown to cover coord as well, or just coord if there isn't a rect yet
+        let rect = rect.unwrap_or(DirtyRect {
+            min: coord,
+            max: coord,
+        });
+        DirtyRect {
+            min: SandboxCoordinate {
+                x: rect.min.x.min(coord.x),
+                y: rect.min.y.min(coord.y),
+            },
+            max: SandboxCoordinate {
+                x: rect.max.x.max(coord.x),
+                y: rect.max.y.max(coord.y),
+            },
+        }
+    }
+}
+
+pub fn chunks_across(sandbox_w: i32) -> i32 {
+    (sandbox_w + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+pub fn chunk_at(sandbox_w: i32, coord: SandboxCoordinate) -> usize {
+    // the index of the chunk coord is in
+    (coord.y / CHUNK_SIZE * chunks_across(sandbox_w) + coord.x / CHUNK_SIZE) as usize
+}
+
+pub fn chunks(sandbox_w: i32, sandbox_h: i32) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    for (cy, y) in (0..sandbox_h).step_by(CHUNK_SIZE as usize).enumerate() {
+        for (cx, x) in (0..sandbox_w).step_by(CHUNK_SIZE as usize).enumerate() {
+            chunks.push(Chunk {
+                index: chunks.len(),
+                origin: SandboxCoordinate { x, y },
+                w: CHUNK_SIZE.min(sandbox_w - x),
+                h: CHUNK_SIZE.min(sandbox_h - y),
+                pass: cx % 2 + 2 * (cy % 2),
+            });
+        }
+    }
+    chunks
+}
+
+pub fn new_atom(element: Element, clock: u32, rng: &mut impl Rng) -> Atom {
+    let mut atom = Atom::new(element, clock);
+    // stagger lifetimes, so that something painted all at once doesn't all disappear at once too
+    atom.lifetime = atom
+        .lifetime
+        .map(|lifetime| rng.gen_range(lifetime / 2..=lifetime).max(1));
+    atom
+}
+
+pub fn replace_atom<A: Storage<Option<Atom>>, T: Storage<f32>>(
+    cells: &mut Cells<A, T>,
+    coord: SandboxCoordinate,
+    element: Option<Element>,
+    clock: u32,
+    rng: &mut impl Rng,
+) {
+    // turns whatever's at coord into a fresh atom of element, or nothing, leaving the cell's
+    // temperature as it was
+    cells.clear_cell(coord);
+    if let Some(element) = element {
+        cells
+            .fill_cell(coord, new_atom(element, clock, rng))
+            .expect("Couldn't fill cell");
+    }
+}
+
+pub struct Sweep<'a> {
+    // either the whole sandbox, or a band of rows with the chunks being swept in it
+    cells: CellsMut<'a>,
+    air: &'a Air,
+    gravity: Gravity,
+    clock: u32,
+    tick_seed: u64,
+    // seeded afresh for each chunk
+    rng: StdRng,
+    // pressure let off by things burning out, which is only added to the air once the pass is
+    // over, since other chunks' air might be being read at the same time
+    pub blasts: Vec<(SandboxCoordinate, f32)>,
+    // for each chunk swept where anything moved, changed or might yet, a rectangle around it
+    pub dirty: Vec<DirtyRect>,
+    stirred: Option<DirtyRect>,
+}
+
+impl<'a> Sweep<'a> {
+    pub fn new(
+        cells: CellsMut<'a>,
+        air: &'a Air,
+        gravity: Gravity,
+        clock: u32,
+        tick_seed: u64,
+    ) -> Self {
+        Sweep {
+            cells,
+            air,
+            gravity,
+            clock,
+            tick_seed,
+            rng: StdRng::seed_from_u64(tick_seed),
+            blasts: vec![],
+            dirty: vec![],
+            stirred: None,
+        }
+    }
+
+    pub fn chunk(&mut self, chunk: &Chunk) {
+        self.rng = StdRng::seed_from_u64(chunk.seed(self.tick_seed));
+        self.sweep_chunk(chunk);
+        self.dirty.extend(self.stirred.take());
+    }
+
+    fn sweep_chunk(&mut self, chunk: &Chunk) {
+        // sweep from the bottom up (or from whichever side gravity pulls towards) so that
+        // falling atoms get out of the way of the ones above them, alternating the direction of
+        // each row (and each tick) so sideways moves aren't biased to one side; that way the
+        // result only depends on where atoms are, not the order they were made in. atoms are
+        // moved in place as the grid is swept, so the clock keeps any atom that moves into a
+        // cell that hasn't been swept yet from being updated again
+        let (down_x, down_y) = self.gravity.orient(0, 1);
+        // sweeping sideways gravity column by column is the same as sweeping the chunk on its
+        // side row by row
+        let sideways = down_y == 0;
+        let (rows, row_len) = if sideways {
+            (chunk.w, chunk.h)
+        } else {
+            (chunk.h, chunk.w)
+        };
+        let from_far_side = if sideways { down_x > 0 } else { down_y > 0 };
+        let (x0, y0) = (chunk.origin.x, chunk.origin.y);
+        for row in 0..rows {
+            let row = if from_far_side { rows - 1 - row } else { row };
+            // rows alternate by where they are in the sandbox rather than in the chunk, so
+            // neighbouring chunks agree
+            let forwards =
+                ((row + if sideways { x0 } else { y0 }) % 2 == 0) == (self.clock % 2 == 1);
+            for i in 0..row_len {
+                let i = if forwards { i } else { row_len - 1 - i };
+                let coord = if sideways {
+                    SandboxCoordinate {
+                        x: x0 + row,
+                        y: y0 + i,
+                    }
+                } else {
+                    SandboxCoordinate {
+                        x: x0 + i,
+                        y: y0 + row,
+                    }
+                };
+                self.update_atom(coord);
+            }
+        }
+    }
+
+    fn stir(&mut self, coord: SandboxCoordinate) {
+        // keeps the chunk, and whichever chunk coord is in, awake for the next tick
+        self.stirred = Some(DirtyRect::including(self.stirred, coord));
+    }
+
+    fn replace_atom(&mut self, coord: SandboxCoordinate, element: Option<Element>) {
+        replace_atom(&mut self.cells, coord, element, self.clock, &mut self.rng);
+        self.stir(coord);
+    }
+
+    fn get_neighbour(&self, coord: SandboxCoordinate) -> Neighbour {
+        // anything beyond the edges of the cells, whether they're the whole sandbox or just a
+        // band of it, is out of bounds
+        if !self.cells.contains(coord) {
+            Neighbour::OutOfBounds
+        } else {
+            match self.cells.get_cell_contents(coord) {
+                Some(atom) => Neighbour::Atom(atom.element()),
+                None => Neighbour::Empty,
+            }
+        }
+    }
+
+    fn get_atom_neighbourhood(&self, coord: SandboxCoordinate) -> Neighbourhood {
+        // seen the way gravity pulls, so below is whichever way that is
+        let gravity = self.gravity;
+        Neighbourhood::new(|dx, dy| {
+            let (dx, dy) = gravity.orient(dx, dy);
+            self.get_neighbour(SandboxCoordinate {
+                x: coord.x + dx,
+                y: coord.y + dy,
+            })
+        })
+    }
+
+    fn update_atom(&mut self, coord: SandboxCoordinate) {
+        let atom = match self.cells.get_cell_contents(coord) {
+            Some(atom) if atom.clock != self.clock => atom,
+            _ => return,
+        };
+        let element = atom.element();
+        // whatever happens to it from here, solids included, it's been updated this tick
+        if let Some(cell) = self.cells.get_cell_contents_mut(coord) {
+            cell.clock = self.clock;
+        }
+        if let Some(lifetime) = atom.lifetime {
+            self.stir(coord);
+            if lifetime <= 1 {
+                self.cells.clear_cell(coord);
+                return;
+            }
+            if let Some(atom) = self.cells.get_cell_contents_mut(coord) {
+                atom.lifetime = Some(lifetime - 1);
+            }
+        }
+        if self.burn(coord, element) {
+            return;
+        }
+        if element.is_solid() {
+            // solids never move, so don't bother looking around them
+            return;
+        }
+        let nh = self.get_atom_neighbourhood(coord);
+        if self.react(coord, element, &nh) {
+            return;
+        }
+        let mut atom = atom;
+        let gravity = self.gravity;
+        let weightless = gravity.acceleration() == 0.0;
+        let mut target = coord;
+        let mut falling = false;
+        let mut rising = false;
+        // optim: if neighbourhood is obviously full dont bother doing anything
+        if element.could_move(&nh) {
+            let (dx, dy) = atom.calculate_move(&nh, weightless, &mut self.rng);
+            // a liquid that can't fall only flows sideways towards somewhere it can, so a puddle
+            // comes to rest almost level instead of sloshing back and forth across itself for
+            // ever
+            let liquid = element.properties().state == MatterState::Liquid && !weightless;
+            let flowing = liquid && self.could_flow(element, coord, &nh);
+            let dx = if liquid && dy == 0 && !flowing { 0 } else { dx };
+            // falling atoms pick up speed, and ones that have landed keep some of it going
+            // sideways; gases just drift about, and don't keep any speed from tick to tick
+            let (dx, dy) = if element.is_gas() {
+                (dx, dy)
+            } else if dy > 0 {
+                falling = true;
+                (dx, dy * atom.fall(gravity.acceleration()))
+            } else {
+                (dx + atom.slide(), dy)
+            };
+            rising = dy < 0;
+            let (dx, dy) = gravity.orient(dx, dy);
+            // and on top of wherever it was going, the wind might blow it somewhere else
+            let (push_x, push_y) = self.air.push(element, coord, &mut self.rng);
+            target.x += dx + push_x;
+            target.y += dy + push_y;
+            // a viscous liquid can hold still one tick and flow the next, so one with somewhere
+            // to go is never settled
+            if flowing {
+                self.stir(coord);
+            }
+        } else {
+            atom.stop();
+        }
+        // never reach further than the margin round a chunk, so that chunks swept at the same
+        // time can't get in each other's way
+        target.x = target.x.clamp(coord.x - MAX_REACH, coord.x + MAX_REACH);
+        target.y = target.y.clamp(coord.y - MAX_REACH, coord.y + MAX_REACH);
+        let next_coord = self.trace_path(element, coord, target, rising);
+        if falling && next_coord != target {
+            atom.land(&mut self.rng);
+        } else if !falling && next_coord != target {
+            atom.stop();
+        }
+        if let Some(cell) = self.cells.get_cell_contents_mut(coord) {
+            cell.velocity = atom.velocity;
+        }
+        if target != coord || atom.velocity != Vec2::ZERO {
+            self.stir(coord);
+        }
+        if next_coord != coord {
+            self.stir(next_coord);
+            // swapping both moves the atom and, if it's sinking, lifts the lighter atom it
+            // displaced into the space it left
+            self.cells.swap_cells(coord, next_coord);
+        }
+    }
+
+    fn could_flow(&self, element: Element, coord: SandboxCoordinate, nh: &Neighbourhood) -> bool {
+        // whether a liquid has anywhere lower to go: straight down or diagonally, or along the
+        // surface it's on, as far as anything can reach, to somewhere it can fall from
+        if [nh.below_left(), nh.below(), nh.below_right()]
+            .iter()
+            .any(|&neighbour| element.can_displace(neighbour))
+        {
+            return true;
+        }
+        let neighbour = |dx, dy| {
+            let (dx, dy) = self.gravity.orient(dx, dy);
+            self.get_neighbour(SandboxCoordinate {
+                x: coord.x + dx,
+                y: coord.y + dy,
+            })
+        };
+        [-1, 1].iter().any(|&side| {
+            for i in 1..=MAX_REACH {
+                if !element.can_displace(neighbour(side * i, 0)) {
+                    return false;
+                }
+                if element.can_displace(neighbour(side * i, 1)) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    fn burn(&mut self, coord: SandboxCoordinate, element: Element) -> bool {
+        // flammable atoms catch fire from anything alight next to them, then burn for a while,
+        // giving off flames and smoke, until they burn out; returns whether this one has
+        let burns = match &element.properties().burns {
+            Some(burns) => burns,
+            None => return false,
+        };
+        let burning = self
+            .cells
+            .get_cell_contents(coord)
+            .and_then(|atom| atom.burning);
+        match burning {
+            None => {
+                // anything flammable next to a fire might catch from it any tick
+                if self.touching_fire(coord) {
+                    self.stir(coord);
+                    if self.rng.gen::<f32>() < burns.ignition {
+                        if let Some(atom) = self.cells.get_cell_contents_mut(coord) {
+                            atom.burning = Some(burns.duration);
+                        }
+                    }
+                }
+                false
+            }
+            Some(left) if left <= 1 => {
+                self.replace_atom(coord, burns.leaves);
+                self.blasts.push((coord, burns.blast));
+                true
+            }
+            Some(left) => {
+                self.stir(coord);
+                if let Some(atom) = self.cells.get_cell_contents_mut(coord) {
+                    atom.burning = Some(left - 1);
+                }
+                if !burns.gives_off.is_empty() && self.rng.gen::<f32>() < GIVE_OFF_CHANCE {
+                    let product = burns.gives_off[self.rng.gen_range(0..burns.gives_off.len())];
+                    // above being whichever way is up with gravity as it is
+                    let (dx, dy) = self.gravity.orient(self.rng.gen_range(-1..=1), -1);
+                    let above = SandboxCoordinate {
+                        x: coord.x + dx,
+                        y: coord.y + dy,
+                    };
+                    if self.get_neighbour(above) == Neighbour::Empty {
+                        self.replace_atom(above, Some(product));
+                        self.cells
+                            .set_temperature(above, product.properties().temperature);
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn touching_fire(&self, coord: SandboxCoordinate) -> bool {
+        // whether any of the atoms around coord is burning, or sets things alight
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .map(|(dx, dy)| SandboxCoordinate {
+                x: coord.x + dx,
+                y: coord.y + dy,
+            })
+            .filter(|&coord| self.cells.contains(coord))
+            .filter_map(|coord| self.cells.get_cell_contents(coord))
+            .any(|atom| atom.is_alight())
+    }
+
+    fn react(&mut self, coord: SandboxCoordinate, element: Element, nh: &Neighbourhood) -> bool {
+        // gives each neighbour in turn a chance to react with this atom, stopping at the first
+        // that does; the products are made on this tick's clock, so they sit still until the next
+        let registry = ElementRegistry::global();
+        for ((dx, dy), neighbour) in nh.iter() {
+            let reaction = match neighbour {
+                Neighbour::Atom(other) => registry.reaction(element, other),
+                _ => None,
+            };
+            if let Some(reaction) = reaction {
+                // and the same goes for anything next to something it could react with
+                self.stir(coord);
+                if self.rng.gen::<f32>() < reaction.probability {
+                    let [product, other_product] = reaction.products;
+                    let (dx, dy) = self.gravity.orient(dx, dy);
+                    let other_coord = SandboxCoordinate {
+                        x: coord.x + dx,
+                        y: coord.y + dy,
+                    };
+                    // products start out at their own temperature, so that, say, steam made by
+                    // cold water is hot enough to be steam
+                    for &(coord, product) in &[(coord, product), (other_coord, other_product)] {
+                        self.replace_atom(coord, product);
+                        if let Some(product) = product {
+                            self.cells
+                                .set_temperature(coord, product.properties().temperature);
+                        }
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn trace_path(
+        &self,
+        element: Element,
+        from: SandboxCoordinate,
+        to: SandboxCoordinate,
+        rising: bool,
+    ) -> SandboxCoordinate {
+        // walks from towards to, stopping short of the first cell that's out of bounds or holds
+        // something element can't displace (which might have moved in this tick), or stopping
+        // at the first lighter atom it can sink into (or heavier one it can rise into); returns
+        // where the walk ended
+        let mut end = from;
+        for coord in brush::line(from, to).into_iter().skip(1) {
+            match self.get_neighbour(coord) {
+                Neighbour::Empty => end = coord,
+                neighbour if rising && element.can_rise_into(neighbour) => return coord,
+                neighbour if !rising && element.can_displace(neighbour) => return coord,
+                _ => break,
+            }
+        }
+        end
+    }
+}
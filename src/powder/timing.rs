@@ -0,0 +1,167 @@
+// Decides how many simulation ticks to run each frame. The simulation runs at a fixed tick rate
+// (scaled by the speed multiplier) however fast frames are being drawn, catching up on missed
+// ticks up to a limit so that a slow frame doesn't snowball into slower and slower ones.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+// never run more than this much simulated time in one frame; anything past it is dropped
+const MAX_CATCH_UP_SECONDS: f32 = 0.1;
+// how many frames the measured tick rate is averaged over
+const TICK_RATE_FRAMES: usize = 60;
+
+#[derive(Debug)]
+pub struct Timing {
+    pub paused: bool,
+    // ticks queued up by single-stepping while paused
+    steps: u32,
+    ticks_per_second: u32,
+    speed_index: usize,
+    // (ticks run, frame length) for recent frames
+    recent_frames: VecDeque<(u32, Duration)>,
+}
+
+impl Timing {
+    pub fn new(ticks_per_second: u32) -> Self {
+        Timing {
+            paused: false,
+            steps: 0,
+            ticks_per_second,
+            speed_index: SPEEDS.iter().position(|&speed| speed == 1.0).unwrap(),
+            recent_frames: VecDeque::with_capacity(TICK_RATE_FRAMES),
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.steps = 0;
+    }
+
+    pub fn step(&mut self) {
+        // stepping while running pauses first, so the step is actually visible
+        self.paused = true;
+        self.steps += 1;
+    }
+
+    pub fn speed(&self) -> f32 {
+        SPEEDS[self.speed_index]
+    }
+
+    pub fn faster(&mut self) {
+        self.speed_index = (self.speed_index + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed_index = self.speed_index.saturating_sub(1);
+    }
+
+    pub fn target_tick_rate(&self) -> u32 {
+        ((self.ticks_per_second as f32 * self.speed()).round() as u32).max(1)
+    }
+
+    pub fn ticks_due(&mut self, mut tick_ready: impl FnMut(u32) -> bool) -> u32 {
+        // tick_ready(rate) should say whether another tick is owed at rate ticks per second,
+        // consuming it if so, i.e. ggez's check_update_time
+        let rate = self.target_tick_rate();
+        let max_ticks = ((rate as f32 * MAX_CATCH_UP_SECONDS) as u32).max(1);
+        let mut ticks = 0;
+        while tick_ready(rate) {
+            if ticks < max_ticks {
+                ticks += 1;
+            }
+            // past the limit, keep consuming owed ticks but drop them
+        }
+        if self.paused {
+            // time still passes while paused, it just isn't simulated
+            std::mem::take(&mut self.steps)
+        } else {
+            ticks
+        }
+    }
+
+    pub fn record_frame(&mut self, ticks: u32, frame_length: Duration) {
+        if self.recent_frames.len() == TICK_RATE_FRAMES {
+            self.recent_frames.pop_front();
+        }
+        self.recent_frames.push_back((ticks, frame_length));
+    }
+
+    pub fn tick_rate(&self) -> f64 {
+        // measured rather than target, so it shows when the simulation can't keep up
+        let ticks: u32 = self.recent_frames.iter().map(|(ticks, _)| ticks).sum();
+        let time: Duration = self.recent_frames.iter().map(|(_, length)| *length).sum();
+        if time.is_zero() {
+            0.0
+        } else {
+            ticks as f64 / time.as_secs_f64()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owed(ticks: u32) -> impl FnMut(u32) -> bool {
+        let mut owed = ticks;
+        move |_rate| {
+            if owed > 0 {
+                owed -= 1;
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    #[test]
+    fn runs_owed_ticks_up_to_catch_up_limit() {
+        let mut timing = Timing::new(60);
+        assert_eq!(timing.ticks_due(owed(0)), 0);
+        assert_eq!(timing.ticks_due(owed(2)), 2);
+        // 60 ticks per second can only catch up 6 ticks in a frame
+        assert_eq!(timing.ticks_due(owed(100)), 6);
+    }
+
+    #[test]
+    fn speed_scales_tick_rate() {
+        let mut timing = Timing::new(60);
+        assert_eq!(timing.target_tick_rate(), 60);
+        for _ in 0..10 {
+            timing.faster();
+        }
+        assert_eq!(timing.speed(), 8.0);
+        assert_eq!(timing.target_tick_rate(), 480);
+        for _ in 0..10 {
+            timing.slower();
+        }
+        assert_eq!(timing.speed(), 0.25);
+        assert_eq!(timing.target_tick_rate(), 15);
+    }
+
+    #[test]
+    fn paused_only_runs_requested_steps() {
+        let mut timing = Timing::new(60);
+        timing.toggle_pause();
+        assert_eq!(timing.ticks_due(owed(3)), 0);
+        timing.step();
+        timing.step();
+        assert_eq!(timing.ticks_due(owed(3)), 2);
+        assert_eq!(timing.ticks_due(owed(3)), 0);
+        timing.toggle_pause();
+        assert_eq!(timing.ticks_due(owed(3)), 3);
+        // stepping while running pauses
+        timing.step();
+        assert!(timing.paused);
+        assert_eq!(timing.ticks_due(owed(3)), 1);
+    }
+
+    #[test]
+    fn measures_tick_rate() {
+        let mut timing = Timing::new(60);
+        assert_eq!(timing.tick_rate(), 0.0);
+        timing.record_frame(2, Duration::from_millis(50));
+        timing.record_frame(0, Duration::from_millis(50));
+        assert_eq!(timing.tick_rate(), 20.0);
+    }
+}